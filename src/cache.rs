@@ -0,0 +1,465 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{certs, crl, product::Product, report::SigningKey, tcb::Tcb};
+
+#[derive(Args)]
+pub struct CacheArgs {
+    /// Certificate cache directory [default: $XDG_CACHE_HOME/sev-tool]
+    #[arg(long, global = true)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Neither read from nor write to the certificate cache.
+    #[arg(long, global = true, conflicts_with = "offline")]
+    pub no_cache: bool,
+
+    /// Only use cached certificates, never contact AMD KDS.
+    #[arg(long, global = true)]
+    pub offline: bool,
+}
+
+/// What a cache file holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    Vcek { hw_id: String, tcb: Tcb },
+    CertChain(SigningKey),
+    Crl(SigningKey),
+}
+
+pub struct CacheEntry {
+    pub product: Product,
+    pub kind: EntryKind,
+    pub path: PathBuf,
+    pub modified: SystemTime,
+}
+
+/// On-disk store of KDS downloads, laid out as
+/// `<root>/<product>/cert_chain.pem`, `<root>/<product>/crl.der`,
+/// `<root>/<product>/vcek/<hwid>/<raw tcb>.der`, and the VLEK chain and CRL
+/// under `<root>/<product>/vlek/`.
+pub struct CertCache {
+    root: PathBuf,
+}
+
+impl CertCache {
+    pub fn open(args: &CacheArgs) -> anyhow::Result<Option<Self>> {
+        if args.no_cache {
+            return Ok(None);
+        }
+        let root = match &args.cache_dir {
+            Some(dir) => dir.clone(),
+            None => dirs::cache_dir()
+                .context("No cache directory, pass --cache-dir or --no-cache")?
+                .join("sev-tool"),
+        };
+        Ok(Some(Self { root }))
+    }
+
+    fn product_dir(&self, product: Product) -> PathBuf {
+        self.root.join(product.name())
+    }
+
+    pub fn vcek_path(&self, product: Product, hw_id: &[u8], tcb: Tcb) -> PathBuf {
+        self.product_dir(product)
+            .join("vcek")
+            .join(hex::encode(hw_id))
+            .join(format!("{}.der", hex::encode(tcb.to_raw(product))))
+    }
+
+    /// VCEK chains and CRLs live at the product root; VLEK ones under `vlek/`.
+    fn key_dir(&self, product: Product, key: SigningKey) -> PathBuf {
+        match key {
+            SigningKey::Vlek => self.product_dir(product).join("vlek"),
+            _ => self.product_dir(product),
+        }
+    }
+
+    fn cert_chain_path(&self, product: Product, key: SigningKey) -> PathBuf {
+        self.key_dir(product, key).join("cert_chain.pem")
+    }
+
+    fn crl_path(&self, product: Product, key: SigningKey) -> PathBuf {
+        self.key_dir(product, key).join("crl.der")
+    }
+
+    pub fn get_vcek(&self, product: Product, hw_id: &[u8], tcb: Tcb) -> Option<Vec<u8>> {
+        read_if_present(&self.vcek_path(product, hw_id, tcb))
+    }
+
+    pub fn put_vcek(
+        &self,
+        product: Product,
+        hw_id: &[u8],
+        tcb: Tcb,
+        der: &[u8],
+    ) -> anyhow::Result<PathBuf> {
+        let path = self.vcek_path(product, hw_id, tcb);
+        certs::write_atomic(&path, der)?;
+        Ok(path)
+    }
+
+    pub fn get_cert_chain(&self, product: Product, key: SigningKey) -> Option<Vec<u8>> {
+        read_if_present(&self.cert_chain_path(product, key))
+    }
+
+    pub fn put_cert_chain(
+        &self,
+        product: Product,
+        key: SigningKey,
+        pem: &[u8],
+    ) -> anyhow::Result<PathBuf> {
+        let path = self.cert_chain_path(product, key);
+        certs::write_atomic(&path, pem)?;
+        Ok(path)
+    }
+
+    pub fn get_crl(&self, product: Product, key: SigningKey) -> Option<Vec<u8>> {
+        read_if_present(&self.crl_path(product, key))
+    }
+
+    pub fn put_crl(
+        &self,
+        product: Product,
+        key: SigningKey,
+        der: &[u8],
+    ) -> anyhow::Result<PathBuf> {
+        let path = self.crl_path(product, key);
+        certs::write_atomic(&path, der)?;
+        Ok(path)
+    }
+
+    pub fn entries(&self) -> anyhow::Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+        for product in [Product::Milan, Product::Genoa, Product::Turin] {
+            for key in [SigningKey::Vcek, SigningKey::Vlek] {
+                let chain = self.cert_chain_path(product, key);
+                if chain.is_file() {
+                    entries.push(entry(product, EntryKind::CertChain(key), chain)?);
+                }
+                let crl = self.crl_path(product, key);
+                if crl.is_file() {
+                    entries.push(entry(product, EntryKind::Crl(key), crl)?);
+                }
+            }
+
+            let vcek_dir = self.product_dir(product).join("vcek");
+            for hw_dir in read_dir(&vcek_dir)? {
+                let Some(hw_id) = file_name(&hw_dir) else {
+                    continue;
+                };
+                for path in read_dir(&hw_dir)? {
+                    let Some(tcb) = parse_tcb_file(product, &path) else {
+                        log::warn!("Ignoring unexpected cache file {}", path.display());
+                        continue;
+                    };
+                    let kind = EntryKind::Vcek {
+                        hw_id: hw_id.clone(),
+                        tcb,
+                    };
+                    entries.push(entry(product, kind, path)?);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Remove entries older than `max_age`, or that no longer hold a
+    /// currently valid certificate or CRL. Returns the removed entries.
+    pub fn prune(
+        &self,
+        product: Option<Product>,
+        max_age: Option<Duration>,
+    ) -> anyhow::Result<Vec<CacheEntry>> {
+        let now = SystemTime::now();
+        let mut removed = Vec::new();
+
+        for entry in self.entries()? {
+            if product.is_some_and(|product| product != entry.product) {
+                continue;
+            }
+            let age = now.duration_since(entry.modified).unwrap_or_default();
+            let stale = max_age.is_some_and(|max_age| age > max_age);
+            let valid = match entry.kind {
+                EntryKind::Crl(_) => holds_fresh_crl(&entry.path),
+                _ => holds_valid_certs(&entry.path),
+            };
+            if stale || !valid {
+                fs::remove_file(&entry.path)
+                    .with_context(|| format!("Failed to remove {}", entry.path.display()))?;
+                removed.push(entry);
+            }
+        }
+
+        Ok(removed)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+/// Fail early when `--offline` leaves no way to obtain a certificate.
+pub fn require_online(args: &CacheArgs, what: &str) -> anyhow::Result<()> {
+    if args.offline {
+        bail!("{what} is not cached and --offline forbids contacting AMD KDS");
+    }
+    Ok(())
+}
+
+fn read_if_present(path: &Path) -> Option<Vec<u8>> {
+    let bytes = fs::read(path).ok()?;
+    log::info!("Using cached {}", path.display());
+    Some(bytes)
+}
+
+fn read_dir(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    Some(path.file_name()?.to_str()?.to_string())
+}
+
+fn parse_tcb_file(product: Product, path: &Path) -> Option<Tcb> {
+    let name = file_name(path)?;
+    let raw: [u8; 8] = hex::decode(name.strip_suffix(".der")?)
+        .ok()?
+        .try_into()
+        .ok()?;
+    Some(Tcb::from_raw(product, raw))
+}
+
+fn entry(product: Product, kind: EntryKind, path: PathBuf) -> anyhow::Result<CacheEntry> {
+    let modified = fs::metadata(&path)?.modified()?;
+    Ok(CacheEntry {
+        product,
+        kind,
+        path,
+        modified,
+    })
+}
+
+fn holds_valid_certs(path: &Path) -> bool {
+    let Ok(bytes) = fs::read(path) else {
+        return false;
+    };
+    let certs = certs::parse_certs(&bytes).unwrap_or_default();
+    !certs.is_empty()
+        && certs
+            .iter()
+            .all(|cert| certs::is_current(cert).unwrap_or(false))
+}
+
+fn holds_fresh_crl(path: &Path) -> bool {
+    let Ok(bytes) = fs::read(path) else {
+        return false;
+    };
+    crl::parse(&bytes).is_ok_and(|crl| crl::is_stale(&crl).is_ok_and(|stale| !stale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extensions::VcekExtensions, pki::TestCa, testutil::TempDir};
+    use std::sync::OnceLock;
+
+    const CHIP: [u8; 64] = [0x42; 64];
+    const TCB: Tcb = Tcb {
+        fmc: None,
+        bootloader: 3,
+        tee: 0,
+        snp: 22,
+        microcode: 210,
+    };
+
+    fn ca() -> &'static TestCa {
+        static CA: OnceLock<TestCa> = OnceLock::new();
+        CA.get_or_init(|| TestCa::generate(Product::Genoa).unwrap())
+    }
+
+    fn args(dir: &TempDir, offline: bool) -> CacheArgs {
+        CacheArgs {
+            cache_dir: Some(dir.path().to_path_buf()),
+            no_cache: false,
+            offline,
+        }
+    }
+
+    /// A cache holding a valid VCEK chain and VCEK, a fresh VCEK CRL and a
+    /// stale VLEK CRL, all for Genoa.
+    fn filled(dir: &TempDir) -> CertCache {
+        let cache = CertCache::open(&args(dir, false)).unwrap().unwrap();
+        let ca = ca();
+        let chain = [ca.ask.to_pem().unwrap(), ca.ark.to_pem().unwrap()].concat();
+        let (vcek, _) = ca
+            .issue_vcek(&VcekExtensions {
+                product_name: Product::Genoa.vcek_product_name().to_string(),
+                bl_spl: TCB.bootloader,
+                tee_spl: TCB.tee,
+                snp_spl: TCB.snp,
+                ucode_spl: TCB.microcode,
+                fmc_spl: None,
+                hw_id: Some(CHIP.to_vec()),
+                csp_id: None,
+            })
+            .unwrap();
+        let fresh = ca.crl(&[], 30).unwrap().to_der().unwrap();
+        let stale = ca.crl(&[], -1).unwrap().to_der().unwrap();
+
+        let genoa = Product::Genoa;
+        cache
+            .put_cert_chain(genoa, SigningKey::Vcek, &chain)
+            .unwrap();
+        cache
+            .put_vcek(genoa, &CHIP, TCB, &vcek.to_der().unwrap())
+            .unwrap();
+        cache.put_crl(genoa, SigningKey::Vcek, &fresh).unwrap();
+        cache.put_crl(genoa, SigningKey::Vlek, &stale).unwrap();
+        cache
+    }
+
+    fn relative(cache: &CertCache, path: &Path) -> String {
+        path.strip_prefix(cache.root())
+            .unwrap()
+            .display()
+            .to_string()
+    }
+
+    #[test]
+    fn layout() {
+        let dir = TempDir::new("cache-layout");
+        let cache = CertCache::open(&args(&dir, false)).unwrap().unwrap();
+        assert_eq!(cache.root(), dir.path());
+
+        let turin = Tcb {
+            fmc: Some(1),
+            ..TCB
+        };
+        let paths = [
+            cache.put_cert_chain(Product::Genoa, SigningKey::Vcek, b"chain"),
+            cache.put_cert_chain(Product::Genoa, SigningKey::Vlek, b"vlek chain"),
+            cache.put_crl(Product::Milan, SigningKey::Vcek, b"crl"),
+            cache.put_crl(Product::Milan, SigningKey::Vlek, b"vlek crl"),
+            cache.put_vcek(Product::Genoa, &CHIP, TCB, b"vcek"),
+            cache.put_vcek(Product::Turin, &CHIP[..8], turin, b"turin vcek"),
+        ]
+        .map(|path| relative(&cache, &path.unwrap()));
+        assert_eq!(
+            paths,
+            [
+                "Genoa/cert_chain.pem".to_string(),
+                "Genoa/vlek/cert_chain.pem".to_string(),
+                "Milan/crl.der".to_string(),
+                "Milan/vlek/crl.der".to_string(),
+                format!("Genoa/vcek/{}/03000000000016d2.der", "42".repeat(64)),
+                "Turin/vcek/4242424242424242/01030016000000d2.der".to_string(),
+            ]
+        );
+
+        let genoa = Product::Genoa;
+        assert_eq!(
+            cache.get_cert_chain(genoa, SigningKey::Vlek).unwrap(),
+            b"vlek chain"
+        );
+        assert_eq!(cache.get_vcek(genoa, &CHIP, TCB).unwrap(), b"vcek");
+        assert_eq!(cache.get_crl(genoa, SigningKey::Vcek), None);
+        assert_eq!(cache.get_vcek(genoa, &CHIP, Tcb { snp: 23, ..TCB }), None);
+    }
+
+    #[test]
+    fn lists_entries() {
+        let dir = TempDir::new("cache-list");
+        let cache = filled(&dir);
+        dir.write("Genoa/vcek/unexpected.txt", "");
+        let kinds: Vec<_> = cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.product, entry.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (Product::Genoa, EntryKind::CertChain(SigningKey::Vcek)),
+                (Product::Genoa, EntryKind::Crl(SigningKey::Vcek)),
+                (Product::Genoa, EntryKind::Crl(SigningKey::Vlek)),
+                (
+                    Product::Genoa,
+                    EntryKind::Vcek {
+                        hw_id: "42".repeat(64),
+                        tcb: TCB
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn prunes_invalid_entries() {
+        let dir = TempDir::new("cache-prune");
+        let cache = filled(&dir);
+        let garbage = cache
+            .put_vcek(Product::Genoa, &[0x43; 64], TCB, b"not a certificate")
+            .unwrap();
+
+        assert!(cache.prune(Some(Product::Milan), None).unwrap().is_empty());
+        let removed: Vec<_> = cache
+            .prune(None, Some(Duration::from_secs(3600)))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(
+            removed,
+            [cache.crl_path(Product::Genoa, SigningKey::Vlek), garbage]
+        );
+        assert_eq!(cache.entries().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn prunes_old_entries() {
+        let dir = TempDir::new("cache-age");
+        let cache = filled(&dir);
+        std::thread::sleep(Duration::from_millis(10));
+        let removed = cache
+            .prune(Some(Product::Genoa), Some(Duration::ZERO))
+            .unwrap();
+        assert_eq!(removed.len(), 4);
+        assert!(cache.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn offline_needs_cached_entries() {
+        let dir = TempDir::new("cache-offline");
+        let cache = CertCache::open(&args(&dir, true)).unwrap().unwrap();
+        assert_eq!(cache.get_cert_chain(Product::Genoa, SigningKey::Vcek), None);
+        let err = require_online(&args(&dir, true), "The Genoa ASK certificate chain");
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "The Genoa ASK certificate chain is not cached and --offline forbids contacting AMD KDS"
+        );
+        require_online(&args(&dir, false), "The Genoa ASK certificate chain").unwrap();
+    }
+
+    #[test]
+    fn no_cache() {
+        let args = CacheArgs {
+            cache_dir: None,
+            no_cache: true,
+            offline: false,
+        };
+        assert!(CertCache::open(&args).unwrap().is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::{bail, Context};
+use openssl::{
+    asn1::Asn1Time,
+    pkey::Id,
+    sha::sha256,
+    x509::{X509NameRef, X509Ref, X509},
+};
+use sev::firmware::host::{CertTableEntry, CertType};
+use std::{fs, io::Write, path::Path};
+use uuid::Uuid;
+
+use crate::{crl, product::Product, report::SigningKey};
+
+/// GUID of a VLEK in an extended report certificate table (GHCB spec).
+const VLEK_GUID: Uuid = uuid::uuid!("a8074bc2-a25a-483e-aae6-39c045a0b8a1");
+/// GUID of the ARK's CRL in an extended report certificate table.
+const CRL_GUID: Uuid = uuid::uuid!("92f81bc3-5811-4d3d-97ff-d19f88dc67ea");
+
+/// Parse a certificate in either DER or PEM encoding.
+pub fn parse_cert(bytes: &[u8]) -> anyhow::Result<X509> {
+    if bytes.starts_with(b"-----BEGIN") {
+        X509::from_pem(bytes).context("Invalid PEM certificate")
+    } else {
+        X509::from_der(bytes).context("Invalid DER certificate")
+    }
+}
+
+/// Parse a single DER certificate or one or more concatenated PEM ones.
+pub fn parse_certs(bytes: &[u8]) -> anyhow::Result<Vec<X509>> {
+    if bytes.starts_with(b"-----BEGIN") {
+        X509::stack_from_pem(bytes).context("Invalid PEM certificate bundle")
+    } else {
+        Ok(vec![parse_cert(bytes)?])
+    }
+}
+
+/// Load a DER or PEM certificate from disk.
+pub fn load_cert(path: &Path) -> anyhow::Result<X509> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read certificate {}", path.display()))?;
+    parse_cert(&bytes).with_context(|| format!("Failed to load certificate {}", path.display()))
+}
+
+/// SHA-256 over the DER SubjectPublicKeyInfo, as lowercase hex.
+pub fn key_fingerprint(cert: &X509Ref) -> anyhow::Result<String> {
+    let spki = cert.public_key()?.public_key_to_der()?;
+    Ok(hex::encode(sha256(&spki)))
+}
+
+/// Format a distinguished name as `CN=..., O=...`, most specific first.
+pub fn name_to_string(name: &X509NameRef) -> String {
+    let mut parts: Vec<String> = name
+        .entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry.data().to_string().unwrap_or_default();
+            format!("{key}={value}")
+        })
+        .collect();
+    parts.reverse();
+    parts.join(", ")
+}
+
+/// Describe the certificate's public key, e.g. `RSA 4096` or `EC secp384r1`.
+pub fn key_algorithm(cert: &X509Ref) -> anyhow::Result<String> {
+    let key = cert.public_key()?;
+    Ok(match key.id() {
+        Id::RSA => format!("RSA {}", key.bits()),
+        Id::EC => {
+            let curve = key.ec_key()?.group().curve_name();
+            let curve = curve.and_then(|nid| nid.short_name().ok()).unwrap_or("?");
+            format!("EC {curve}")
+        }
+        id => format!("{id:?} {}", key.bits()),
+    })
+}
+
+/// AMD's ARK and ASK as shipped with the `sev` crate, where available.
+pub fn builtin_ca(product: Product) -> anyhow::Result<Option<(X509, X509)>> {
+    use sev::certs::snp::builtin::{genoa, milan};
+
+    let (ark, ask) = match product {
+        Product::Milan => (milan::ARK, milan::ASK),
+        Product::Genoa => (genoa::ARK, genoa::ASK),
+        Product::Turin => return Ok(None),
+    };
+    Ok(Some((X509::from_pem(ark)?, X509::from_pem(ask)?)))
+}
+
+/// Split a KDS `cert_chain` PEM bundle into its ASK and ARK.
+pub fn split_cert_chain(pem: &[u8]) -> anyhow::Result<(X509, X509)> {
+    let certs = X509::stack_from_pem(pem).context("Invalid PEM certificate chain")?;
+    if certs.len() != 2 {
+        bail!(
+            "Expected 2 certificates in the chain, found {}",
+            certs.len()
+        );
+    }
+
+    // The ARK is the self-issued one; KDS lists the ASK first.
+    let (ark, ask): (Vec<X509>, Vec<X509>) = certs.into_iter().partition(|cert| {
+        cert.subject_name()
+            .try_cmp(cert.issuer_name())
+            .is_ok_and(|order| order.is_eq())
+    });
+    match (ask.into_iter().next(), ark.into_iter().next()) {
+        (Some(ask), Some(ark)) => Ok((ask, ark)),
+        _ => bail!("Certificate chain must hold exactly one ASK and one self-issued ARK"),
+    }
+}
+
+/// True if the current time lies within the certificate's validity period.
+pub fn is_current(cert: &X509Ref) -> anyhow::Result<bool> {
+    let now = Asn1Time::days_from_now(0)?;
+    Ok(cert.not_before().compare(&now)?.is_le() && cert.not_after().compare(&now)?.is_ge())
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it into
+/// place, so `path` is either left untouched or fully replaced.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let name = path.file_name().context("Output path has no file name")?;
+    let tmp = dir.join(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The certificate table type of `key`'s own certificate.
+pub fn cert_type(key: SigningKey) -> CertType {
+    match key {
+        SigningKey::Vlek => CertType::OTHER(VLEK_GUID),
+        _ => CertType::VCEK,
+    }
+}
+
+/// The certificate table type of the CRL.
+pub fn crl_type() -> CertType {
+    CertType::OTHER(CRL_GUID)
+}
+
+/// A readable name for a certificate table entry of `cert_type`; the ASK slot
+/// is named after `key`'s issuer. Unknown entries keep their GUID.
+pub fn table_entry_name(cert_type: &CertType, key: SigningKey) -> String {
+    match cert_type {
+        CertType::ARK => "ARK".to_string(),
+        CertType::ASK => key.issuer_name().to_string(),
+        CertType::VCEK => "VCEK".to_string(),
+        CertType::OTHER(guid) if *guid == VLEK_GUID => "VLEK".to_string(),
+        CertType::OTHER(guid) if *guid == CRL_GUID => "CRL".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// The raw contents of the `cert_type` entry in a certificate table.
+pub fn table_entry<'a>(entries: &'a [CertTableEntry], cert_type: &CertType) -> Option<&'a [u8]> {
+    entries
+        .iter()
+        .find(|entry| entry.cert_type == *cert_type)
+        .map(|entry| entry.data())
+}
+
+/// The certificate of `cert_type` in an extended report certificate table.
+/// Hosts put the ASVK of a VLEK in the ASK slot.
+pub fn from_cert_table(
+    entries: &[CertTableEntry],
+    cert_type: &CertType,
+) -> anyhow::Result<Option<X509>> {
+    table_entry(entries, cert_type)
+        .map(parse_cert)
+        .transpose()
+        .with_context(|| {
+            format!(
+                "Invalid {} entry in the certificate table",
+                cert_type.to_string()
+            )
+        })
+}
+
+/// The file a certificate table entry is saved as, and its contents. Names
+/// match what fetch-vcek, fetch-ca, fetch-crl and verify use: ark.pem,
+/// ask.pem or asvk.pem, VCEK.bin or vlek.der, and crl.der. Certificates are
+/// re-encoded to match; unknown entries are kept as-is in `<guid>.bin`.
+pub fn table_entry_file(
+    entry: &CertTableEntry,
+    key: SigningKey,
+) -> anyhow::Result<(String, Vec<u8>)> {
+    let data = entry.data();
+    let file = match &entry.cert_type {
+        CertType::ARK => ("ark.pem".to_string(), parse_cert(data)?.to_pem()?),
+        CertType::ASK => (
+            format!("{}.pem", key.issuer_name().to_lowercase()),
+            parse_cert(data)?.to_pem()?,
+        ),
+        CertType::VCEK => ("VCEK.bin".to_string(), parse_cert(data)?.to_der()?),
+        CertType::OTHER(guid) if *guid == VLEK_GUID => {
+            ("vlek.der".to_string(), parse_cert(data)?.to_der()?)
+        }
+        CertType::OTHER(guid) if *guid == CRL_GUID => {
+            ("crl.der".to_string(), crl::parse(data)?.to_der()?)
+        }
+        other => (format!("{}.bin", other.to_string()), data.to_vec()),
+    };
+    Ok(file)
+}
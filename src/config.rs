@@ -0,0 +1,17 @@
+//! Loading of the TOML or JSON files that configure verification.
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use std::{fs, path::Path};
+
+/// Load the `kind` file at `path`, e.g. a TCB policy, as JSON if its name
+/// ends in .json and TOML otherwise.
+pub fn load_config<T: DeserializeOwned>(path: &Path, kind: &str) -> anyhow::Result<T> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {kind} {}", path.display()))?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&text).with_context(|| format!("Invalid {kind} {}", path.display()))
+    } else {
+        toml::from_str(&text).with_context(|| format!("Invalid {kind} {}", path.display()))
+    }
+}
@@ -0,0 +1,223 @@
+//! Revocation checking against AMD's certificate revocation lists.
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use openssl::{
+    asn1::Asn1Time,
+    x509::{CrlStatus, X509Crl, X509CrlRef, X509Ref},
+};
+use std::cmp::Ordering;
+
+use crate::{report::SigningKey, verify};
+
+/// How to treat a CRL that cannot be obtained, does not verify or is past
+/// its nextUpdate. A revoked certificate is always an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CrlPolicy {
+    /// Do not check revocation at all.
+    Skip,
+    /// Warn and continue.
+    Warn,
+    /// Fail verification.
+    Strict,
+}
+
+impl CrlPolicy {
+    /// Report a CRL problem according to the policy. Returns `Ok(false)`
+    /// when the problem was only warned about.
+    pub fn handle(self, error: anyhow::Error) -> anyhow::Result<bool> {
+        match self {
+            CrlPolicy::Strict => Err(error),
+            _ => {
+                log::warn!("{error:#}");
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Parse a CRL in either DER or PEM encoding.
+pub fn parse(bytes: &[u8]) -> anyhow::Result<X509Crl> {
+    if bytes.starts_with(b"-----BEGIN") {
+        X509Crl::from_pem(bytes).context("Invalid PEM CRL")
+    } else {
+        X509Crl::from_der(bytes).context("Invalid DER CRL")
+    }
+}
+
+/// True once the current time is past the CRL's nextUpdate.
+pub fn is_stale(crl: &X509CrlRef) -> anyhow::Result<bool> {
+    let Some(next_update) = crl.next_update() else {
+        return Ok(false);
+    };
+    let now = Asn1Time::days_from_now(0)?;
+    Ok(next_update.compare(&now)? == Ordering::Less)
+}
+
+/// Verify `crl` against whichever of the ARK or ASK issued it, then refuse
+/// any certificate from that issuer whose serial it lists. AMD's KDS CRLs
+/// are signed by the ARK and so cover the ASK or ASVK. Returns false if the
+/// policy let a CRL problem through.
+pub fn check(
+    crl: &X509CrlRef,
+    ark: &X509Ref,
+    ask: &X509Ref,
+    vcek: &X509Ref,
+    key: SigningKey,
+    policy: CrlPolicy,
+) -> anyhow::Result<bool> {
+    let issued_by = |cert: &X509Ref| {
+        crl.issuer_name()
+            .try_cmp(cert.subject_name())
+            .is_ok_and(|order| order.is_eq())
+    };
+    let Some(issuer) = [ark, ask].into_iter().find(|cert| issued_by(cert)) else {
+        return policy.handle(anyhow::anyhow!(
+            "CRL was not issued by the ARK or the {}",
+            key.issuer_name()
+        ));
+    };
+
+    if let Err(e) = verify::crl_signed_by(issuer, crl) {
+        return policy.handle(e.context("CRL signature verification failed"));
+    }
+    let mut complete = true;
+    if is_stale(crl)? {
+        let next_update = crl.next_update().map(ToString::to_string);
+        complete = policy.handle(anyhow::anyhow!(
+            "CRL is stale, its nextUpdate was {}",
+            next_update.unwrap_or_default()
+        ))?;
+    }
+
+    let key_name = key.to_string();
+    for (name, cert) in [(key.issuer_name(), ask), (key_name.as_str(), vcek)] {
+        let same_issuer = cert
+            .issuer_name()
+            .try_cmp(crl.issuer_name())
+            .is_ok_and(|order| order.is_eq());
+        if !same_issuer {
+            continue;
+        }
+        if let CrlStatus::Revoked(entry) = crl.get_by_serial(cert.serial_number()) {
+            let serial = cert.serial_number().to_bn()?.to_hex_str()?.to_string();
+            bail!(
+                "{name} serial 0x{serial} was revoked on {}",
+                entry.revocation_date()
+            );
+        }
+    }
+
+    Ok(complete)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extensions::VcekExtensions, pki::TestCa, product::Product};
+    use openssl::x509::X509;
+    use std::sync::OnceLock;
+
+    /// The CA under test, another with the same names but other keys, and a
+    /// Milan CA with other names.
+    fn cas() -> &'static (TestCa, TestCa, TestCa) {
+        static CAS: OnceLock<(TestCa, TestCa, TestCa)> = OnceLock::new();
+        CAS.get_or_init(|| {
+            (
+                TestCa::generate(Product::Genoa).unwrap(),
+                TestCa::generate(Product::Genoa).unwrap(),
+                TestCa::generate(Product::Milan).unwrap(),
+            )
+        })
+    }
+
+    fn vcek() -> &'static X509 {
+        static VCEK: OnceLock<X509> = OnceLock::new();
+        VCEK.get_or_init(|| {
+            let extensions = VcekExtensions {
+                product_name: Product::Genoa.vcek_product_name().to_string(),
+                bl_spl: 3,
+                tee_spl: 0,
+                snp_spl: 22,
+                ucode_spl: 210,
+                fmc_spl: None,
+                hw_id: Some(vec![0x42; 64]),
+                csp_id: None,
+            };
+            cas().0.issue_vcek(&extensions).unwrap().0
+        })
+    }
+
+    fn check_with(crl: &X509CrlRef, policy: CrlPolicy) -> anyhow::Result<bool> {
+        let ca = &cas().0;
+        check(crl, &ca.ark, &ca.ask, vcek(), SigningKey::Vcek, policy)
+    }
+
+    #[test]
+    fn parse_der_and_pem() {
+        let crl = cas().0.crl(&[], 30).unwrap();
+        for bytes in [crl.to_der().unwrap(), crl.to_pem().unwrap()] {
+            assert_eq!(
+                parse(&bytes).unwrap().to_der().unwrap(),
+                crl.to_der().unwrap()
+            );
+        }
+        assert_eq!(
+            parse(b"not a crl").err().unwrap().to_string(),
+            "Invalid DER CRL"
+        );
+        assert_eq!(
+            parse(b"-----BEGIN X509 CRL-----\n")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Invalid PEM CRL"
+        );
+    }
+
+    #[test]
+    fn fresh_crl_is_complete() {
+        let crl = cas().0.crl(&[], 30).unwrap();
+        assert!(!is_stale(&crl).unwrap());
+        assert!(check_with(&crl, CrlPolicy::Strict).unwrap());
+    }
+
+    #[test]
+    fn stale_crl() {
+        let crl = cas().0.crl(&[], -1).unwrap();
+        assert!(is_stale(&crl).unwrap());
+        assert!(!check_with(&crl, CrlPolicy::Warn).unwrap());
+        let err = check_with(&crl, CrlPolicy::Strict).unwrap_err();
+        assert!(err.to_string().starts_with("CRL is stale"), "{err}");
+    }
+
+    #[test]
+    fn crl_signed_by_wrong_key() {
+        let crl = cas().1.crl(&[], 30).unwrap();
+        assert!(!check_with(&crl, CrlPolicy::Warn).unwrap());
+        let err = check_with(&crl, CrlPolicy::Strict).unwrap_err();
+        assert_eq!(err.to_string(), "CRL signature verification failed");
+    }
+
+    #[test]
+    fn crl_from_other_issuer() {
+        let crl = cas().2.crl(&[], 30).unwrap();
+        assert!(!check_with(&crl, CrlPolicy::Warn).unwrap());
+        let err = check_with(&crl, CrlPolicy::Strict).unwrap_err();
+        assert_eq!(err.to_string(), "CRL was not issued by the ARK or the ASK");
+    }
+
+    #[test]
+    fn revoked_despite_policy() {
+        let ca = &cas().0;
+        let ask = ca.ask.serial_number().to_bn().unwrap();
+        let vcek = vcek().serial_number().to_bn().unwrap();
+        for crl in [
+            ca.crl(&[ask], -1).unwrap(),
+            ca.issue_crl(&ca.ask, &ca.ask_key, &[vcek], 30).unwrap(),
+        ] {
+            let err = check_with(&crl, CrlPolicy::Warn).unwrap_err();
+            assert!(err.to_string().contains("was revoked on"), "{err}");
+        }
+    }
+}
@@ -0,0 +1,601 @@
+//! Every attestation report field decoded into the stable structure behind
+//! `--format`, described by schema/report.schema.json.
+
+use serde::Serialize;
+use sev::firmware::guest::AttestationReport;
+use std::fmt;
+
+use crate::{
+    product::Product,
+    report::{self, SigningKey},
+    tcb::Tcb,
+};
+
+/// Offset of the little-endian ECDSA R and S components, 72 bytes each.
+const SIGNATURE_R_OFFSET: usize = 0x2A0;
+const SIGNATURE_S_OFFSET: usize = 0x2E8;
+/// Size of a P-384 scalar.
+const P384_LEN: usize = 48;
+
+#[derive(Debug, Serialize)]
+pub struct DecodedReport {
+    pub product: Product,
+    pub version: u32,
+    pub guest_svn: u32,
+    pub policy: GuestPolicy,
+    pub family_id: String,
+    pub image_id: String,
+    pub vmpl: u32,
+    pub signature_algorithm: u32,
+    pub current_tcb: Tcb,
+    pub platform_info: PlatformInfo,
+    pub key_info: KeyInfo,
+    pub report_data: String,
+    pub measurement: String,
+    pub host_data: String,
+    pub id_key_digest: String,
+    pub author_key_digest: String,
+    pub report_id: String,
+    pub report_id_ma: String,
+    pub reported_tcb: Tcb,
+    /// Family, model and stepping; only present from report version 3.
+    pub cpuid: Option<Cpuid>,
+    pub chip_id: String,
+    pub committed_tcb: Tcb,
+    pub current_version: FirmwareVersion,
+    pub committed_version: FirmwareVersion,
+    pub launch_tcb: Tcb,
+    /// Mitigations required at launch and currently applied; only present
+    /// from report version 5.
+    pub launch_mit_vector: Option<u64>,
+    pub current_mit_vector: Option<u64>,
+    pub signature: EcdsaSignature,
+    /// Settings that weaken the guest's protection, for reviewers.
+    pub warnings: Vec<String>,
+}
+
+/// The guest policy the guest was launched with.
+#[derive(Debug, Serialize)]
+pub struct GuestPolicy {
+    pub raw: u64,
+    pub abi_major: u8,
+    pub abi_minor: u8,
+    pub smt_allowed: bool,
+    pub migrate_ma_allowed: bool,
+    pub debug_allowed: bool,
+    pub single_socket: bool,
+    pub cxl_allowed: bool,
+    pub mem_aes_256_xts: bool,
+    pub rapl_disabled: bool,
+    pub ciphertext_hiding: bool,
+    pub page_swap_disabled: bool,
+}
+
+impl GuestPolicy {
+    pub fn from_raw(raw: u64) -> Self {
+        let bit = |n: u32| raw & (1 << n) != 0;
+        Self {
+            raw,
+            abi_minor: raw as u8,
+            abi_major: (raw >> 8) as u8,
+            smt_allowed: bit(16),
+            migrate_ma_allowed: bit(18),
+            debug_allowed: bit(19),
+            single_socket: bit(20),
+            cxl_allowed: bit(21),
+            mem_aes_256_xts: bit(22),
+            rapl_disabled: bit(23),
+            ciphertext_hiding: bit(24),
+            page_swap_disabled: bit(25),
+        }
+    }
+
+    /// Bit 17 is reserved and must be set for the firmware to accept the policy.
+    pub fn reserved_bit_set(&self) -> bool {
+        self.raw & (1 << 17) != 0
+    }
+
+    /// What each policy bit means, as (name, meaning) pairs.
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "ABI minimum",
+                format!("{}.{}", self.abi_major, self.abi_minor),
+            ),
+            ("SMT", allowed(self.smt_allowed).into()),
+            ("Migration agent", allowed(self.migrate_ma_allowed).into()),
+            ("Debug", allowed(self.debug_allowed).into()),
+            ("Single socket", required(self.single_socket).into()),
+            ("CXL", allowed(self.cxl_allowed).into()),
+            ("AES-256-XTS", required(self.mem_aes_256_xts).into()),
+            ("RAPL disable", required(self.rapl_disabled).into()),
+            ("Ciphertext hiding", required(self.ciphertext_hiding).into()),
+            ("Page swap", disabled(self.page_swap_disabled).into()),
+        ]
+    }
+}
+
+/// Platform state at the time the report was generated.
+#[derive(Debug, Serialize)]
+pub struct PlatformInfo {
+    pub raw: u64,
+    pub smt_enabled: bool,
+    pub tsme_enabled: bool,
+    pub ecc_enabled: bool,
+    pub rapl_disabled: bool,
+    pub ciphertext_hiding_enabled: bool,
+    pub alias_check_complete: bool,
+}
+
+impl PlatformInfo {
+    pub fn from_raw(raw: u64) -> Self {
+        let bit = |n: u32| raw & (1 << n) != 0;
+        Self {
+            raw,
+            smt_enabled: bit(0),
+            tsme_enabled: bit(1),
+            ecc_enabled: bit(2),
+            rapl_disabled: bit(3),
+            ciphertext_hiding_enabled: bit(4),
+            alias_check_complete: bit(5),
+        }
+    }
+
+    /// What each platform info bit means, as (name, meaning) pairs.
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("SMT", enabled(self.smt_enabled).into()),
+            ("TSME", enabled(self.tsme_enabled).into()),
+            ("ECC memory", enabled(self.ecc_enabled).into()),
+            ("RAPL", disabled(self.rapl_disabled).into()),
+            (
+                "Ciphertext hiding",
+                enabled(self.ciphertext_hiding_enabled).into(),
+            ),
+            (
+                "Alias check",
+                match self.alias_check_complete {
+                    true => "complete",
+                    false => "not complete",
+                }
+                .into(),
+            ),
+        ]
+    }
+}
+
+/// Which keys the report was signed with and what it discloses about them.
+#[derive(Debug, Serialize)]
+pub struct KeyInfo {
+    pub raw: u32,
+    pub author_key_enabled: bool,
+    pub mask_chip_key: bool,
+    /// Null for a reserved SIGNING_KEY value.
+    pub signing_key: Option<SigningKey>,
+}
+
+impl KeyInfo {
+    /// What each key info field means, as (name, meaning) pairs.
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        let signing_key = match self.signing_key {
+            Some(SigningKey::None) => "none, the report is unsigned".to_string(),
+            Some(key) => key.to_string(),
+            None => format!("reserved value {}", (self.raw >> 2) & 0x7),
+        };
+        vec![
+            ("Signing key", signing_key),
+            (
+                "Chip key",
+                match self.mask_chip_key {
+                    true => "masked (MASK_CHIP_KEY), signature is zero",
+                    false => "used",
+                }
+                .into(),
+            ),
+            (
+                "Author key",
+                match self.author_key_enabled {
+                    true => "digest included",
+                    false => "not used",
+                }
+                .into(),
+            ),
+        ]
+    }
+}
+
+fn allowed(set: bool) -> &'static str {
+    if set {
+        "allowed"
+    } else {
+        "not allowed"
+    }
+}
+
+fn required(set: bool) -> &'static str {
+    if set {
+        "required"
+    } else {
+        "not required"
+    }
+}
+
+fn enabled(set: bool) -> &'static str {
+    if set {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+fn disabled(set: bool) -> &'static str {
+    enabled(!set)
+}
+
+/// What a VMPL value means.
+pub fn describe_vmpl(vmpl: u32) -> &'static str {
+    match vmpl {
+        0 => "most privileged",
+        1..=3 => "less privileged",
+        _ => "invalid",
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Cpuid {
+    pub family: u8,
+    pub model: u8,
+    pub stepping: u8,
+}
+
+/// An SNP firmware version, `major.minor.build`.
+#[derive(Debug, Serialize)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub build: u8,
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
+
+/// The report signature as big-endian hex scalars.
+#[derive(Debug, Serialize)]
+pub struct EcdsaSignature {
+    pub r: String,
+    pub s: String,
+}
+
+impl DecodedReport {
+    pub fn new(report: &AttestationReport, product: Product) -> anyhow::Result<Self> {
+        let bytes = report::to_bytes(report)?;
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+        };
+        let key_info = report::key_info(report)?;
+        let policy = GuestPolicy::from_raw(u64_at(report::POLICY_OFFSET));
+        let key_info = KeyInfo {
+            raw: key_info,
+            author_key_enabled: key_info & 1 != 0,
+            mask_chip_key: key_info & 2 != 0,
+            signing_key: report::signing_key(report).ok(),
+        };
+        let warnings = warnings(&policy, &key_info, report.vmpl);
+        let mit_vectors = report::mit_vectors(report)?;
+        let scalar = |offset: usize| {
+            let mut scalar = bytes[offset..offset + P384_LEN].to_vec();
+            scalar.reverse();
+            hex::encode(scalar)
+        };
+
+        Ok(Self {
+            product,
+            version: report.version,
+            guest_svn: report.guest_svn,
+            policy,
+            family_id: hex::encode(report.family_id),
+            image_id: hex::encode(report.image_id),
+            vmpl: report.vmpl,
+            signature_algorithm: report.sig_algo,
+            current_tcb: Tcb::from_version(product, &report.current_tcb)?,
+            platform_info: PlatformInfo::from_raw(u64_at(report::PLATFORM_INFO_OFFSET)),
+            key_info,
+            report_data: hex::encode(report.report_data),
+            measurement: hex::encode(report.measurement),
+            host_data: hex::encode(report.host_data),
+            id_key_digest: hex::encode(report.id_key_digest),
+            author_key_digest: hex::encode(report.author_key_digest),
+            report_id: hex::encode(report.report_id),
+            report_id_ma: hex::encode(report.report_id_ma),
+            reported_tcb: Tcb::from_version(product, &report.reported_tcb)?,
+            cpuid: report::cpuid(report)?.map(|(family, model, stepping)| Cpuid {
+                family,
+                model,
+                stepping,
+            }),
+            chip_id: hex::encode(report.chip_id),
+            committed_tcb: Tcb::from_version(product, &report.committed_tcb)?,
+            current_version: FirmwareVersion {
+                major: report.current_major,
+                minor: report.current_minor,
+                build: report.current_build,
+            },
+            committed_version: FirmwareVersion {
+                major: report.committed_major,
+                minor: report.committed_minor,
+                build: report.committed_build,
+            },
+            launch_tcb: Tcb::from_version(product, &report.launch_tcb)?,
+            launch_mit_vector: mit_vectors.map(|(launch, _)| launch),
+            current_mit_vector: mit_vectors.map(|(_, current)| current),
+            signature: EcdsaSignature {
+                r: scalar(SIGNATURE_R_OFFSET),
+                s: scalar(SIGNATURE_S_OFFSET),
+            },
+            warnings,
+        })
+    }
+}
+
+/// Settings a reviewer should not accept without a reason.
+fn warnings(policy: &GuestPolicy, key_info: &KeyInfo, vmpl: u32) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut warn = |condition: bool, message: &str| {
+        if condition {
+            warnings.push(message.to_string());
+        }
+    };
+    warn(
+        policy.debug_allowed,
+        "Guest policy allows debugging: the hypervisor can decrypt and modify guest memory",
+    );
+    warn(
+        policy.migrate_ma_allowed,
+        "Guest policy allows a migration agent, which can export guest memory",
+    );
+    warn(
+        !policy.reserved_bit_set(),
+        "Guest policy reserved bit 17 is clear; firmware does not launch guests with such a policy",
+    );
+    warn(
+        key_info.signing_key == Some(SigningKey::None),
+        "Report is unsigned",
+    );
+    warn(
+        key_info.signing_key.is_none(),
+        "KEY_INFO holds a reserved SIGNING_KEY value",
+    );
+    warn(
+        key_info.mask_chip_key,
+        "MASK_CHIP_KEY is set: the report signature is zero and cannot be verified",
+    );
+    warn(vmpl > 3, "VMPL is out of range");
+    warnings
+}
+
+impl fmt::Display for DecodedReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = |label: &str, value: &dyn fmt::Display| {
+            writeln!(f, "{:<20} {value}", format!("{label}:"))
+        };
+
+        line("Product", &self.product)?;
+        line("Version", &self.version)?;
+        line("Guest SVN", &self.guest_svn)?;
+        line("Policy", &format!("{:#x}", self.policy.raw))?;
+        for (name, meaning) in self.policy.describe() {
+            line(&format!("  {name}"), &meaning)?;
+        }
+        line("Family ID", &self.family_id)?;
+        line("Image ID", &self.image_id)?;
+        line(
+            "VMPL",
+            &format!("{} ({})", self.vmpl, describe_vmpl(self.vmpl)),
+        )?;
+        line("Signature algorithm", &self.signature_algorithm)?;
+        line("Current TCB", &self.current_tcb)?;
+        line("Platform info", &format!("{:#x}", self.platform_info.raw))?;
+        for (name, meaning) in self.platform_info.describe() {
+            line(&format!("  {name}"), &meaning)?;
+        }
+        line("Key info", &format!("{:#x}", self.key_info.raw))?;
+        for (name, meaning) in self.key_info.describe() {
+            line(&format!("  {name}"), &meaning)?;
+        }
+        line("Report data", &self.report_data)?;
+        line("Measurement", &self.measurement)?;
+        line("Host data", &self.host_data)?;
+        line("ID key digest", &self.id_key_digest)?;
+        line("Author key digest", &self.author_key_digest)?;
+        line("Report ID", &self.report_id)?;
+        line("Report ID (MA)", &self.report_id_ma)?;
+        line("Reported TCB", &self.reported_tcb)?;
+        if let Some(cpuid) = &self.cpuid {
+            line(
+                "CPUID",
+                &format!(
+                    "family {:#x}, model {:#x}, stepping {:#x}",
+                    cpuid.family, cpuid.model, cpuid.stepping
+                ),
+            )?;
+        }
+        line("Chip ID", &self.chip_id)?;
+        line("Committed TCB", &self.committed_tcb)?;
+        line("Current version", &self.current_version)?;
+        line("Committed version", &self.committed_version)?;
+        line("Launch TCB", &self.launch_tcb)?;
+        if let (Some(launch), Some(current)) = (self.launch_mit_vector, self.current_mit_vector) {
+            line("Launch mitigations", &format!("{launch:#x}"))?;
+            line("Current mitigations", &format!("{current:#x}"))?;
+        }
+        line("Signature R", &self.signature.r)?;
+        line("Signature S", &self.signature.s)?;
+
+        for warning in &self.warnings {
+            writeln!(f, "WARNING: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Flag<T> = (u32, fn(&T) -> bool);
+
+    const POLICY_FLAGS: [Flag<GuestPolicy>; 9] = [
+        (16, |p| p.smt_allowed),
+        (18, |p| p.migrate_ma_allowed),
+        (19, |p| p.debug_allowed),
+        (20, |p| p.single_socket),
+        (21, |p| p.cxl_allowed),
+        (22, |p| p.mem_aes_256_xts),
+        (23, |p| p.rapl_disabled),
+        (24, |p| p.ciphertext_hiding),
+        (25, |p| p.page_swap_disabled),
+    ];
+
+    const PLATFORM_FLAGS: [Flag<PlatformInfo>; 6] = [
+        (0, |p| p.smt_enabled),
+        (1, |p| p.tsme_enabled),
+        (2, |p| p.ecc_enabled),
+        (3, |p| p.rapl_disabled),
+        (4, |p| p.ciphertext_hiding_enabled),
+        (5, |p| p.alias_check_complete),
+    ];
+
+    /// Setting one bit sets exactly its flag.
+    fn check_flags<T>(flags: &[Flag<T>], from_raw: fn(u64) -> T) {
+        for (bit, _) in flags {
+            let decoded = from_raw(1 << bit);
+            for (other, flag) in flags {
+                assert_eq!(
+                    flag(&decoded),
+                    bit == other,
+                    "bit {bit}, flag of bit {other}"
+                );
+            }
+        }
+    }
+
+    /// A signed v2 report with KEY_INFO `key_info`.
+    fn decode(key_info: u32) -> DecodedReport {
+        let mut bytes = vec![0u8; report::REPORT_SIZE];
+        bytes[0] = 2;
+        bytes[report::POLICY_OFFSET..report::POLICY_OFFSET + 8]
+            .copy_from_slice(&0x30000u64.to_le_bytes());
+        bytes[report::KEY_INFO_OFFSET..report::KEY_INFO_OFFSET + 4]
+            .copy_from_slice(&key_info.to_le_bytes());
+        DecodedReport::new(&report::from_bytes(&bytes).unwrap(), Product::Milan).unwrap()
+    }
+
+    fn policy_warnings(raw: u64) -> Vec<String> {
+        let key_info = decode(0).key_info;
+        warnings(&GuestPolicy::from_raw(raw), &key_info, 0)
+    }
+
+    #[test]
+    fn guest_policy_flags() {
+        check_flags(&POLICY_FLAGS, GuestPolicy::from_raw);
+
+        let policy = GuestPolicy::from_raw(0x3_0305);
+        assert_eq!((policy.abi_major, policy.abi_minor), (3, 5));
+        assert!(policy.reserved_bit_set());
+        assert!(!GuestPolicy::from_raw(0x1_0000).reserved_bit_set());
+        assert_eq!(
+            GuestPolicy::from_raw(0xa_0000).describe()[..4],
+            [
+                ("ABI minimum", "0.0".to_string()),
+                ("SMT", "not allowed".to_string()),
+                ("Migration agent", "not allowed".to_string()),
+                ("Debug", "allowed".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn platform_info_flags() {
+        check_flags(&PLATFORM_FLAGS, PlatformInfo::from_raw);
+        let info = PlatformInfo::from_raw(0x25);
+        assert_eq!(
+            info.describe(),
+            [
+                ("SMT", "enabled".to_string()),
+                ("TSME", "disabled".to_string()),
+                ("ECC memory", "enabled".to_string()),
+                ("RAPL", "enabled".to_string()),
+                ("Ciphertext hiding", "disabled".to_string()),
+                ("Alias check", "complete".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_info_fields() {
+        let key_info = decode(0b1).key_info;
+        assert!(key_info.author_key_enabled && !key_info.mask_chip_key);
+        assert_eq!(key_info.signing_key, Some(SigningKey::Vcek));
+
+        let key_info = decode(0b10 | SigningKey::Vlek.to_key_info()).key_info;
+        assert!(!key_info.author_key_enabled && key_info.mask_chip_key);
+        assert_eq!(key_info.signing_key, Some(SigningKey::Vlek));
+
+        assert_eq!(
+            decode(SigningKey::None.to_key_info()).key_info.signing_key,
+            Some(SigningKey::None)
+        );
+        let reserved = decode(3 << 2).key_info;
+        assert_eq!(reserved.signing_key, None);
+        assert_eq!(
+            reserved.describe()[0],
+            ("Signing key", "reserved value 3".to_string())
+        );
+    }
+
+    #[test]
+    fn no_warnings_for_a_plain_guest() {
+        // SMT and the hardening bits are not warned about.
+        assert!(decode(0).warnings.is_empty());
+        assert!(policy_warnings(0x3_0000 | 0x3f << 20).is_empty());
+    }
+
+    #[test]
+    fn policy_warnings_each() {
+        assert_eq!(
+            policy_warnings(0xa_0000),
+            ["Guest policy allows debugging: the hypervisor can decrypt and modify guest memory"]
+        );
+        assert_eq!(
+            policy_warnings(0x7_0000),
+            ["Guest policy allows a migration agent, which can export guest memory"]
+        );
+        assert_eq!(
+            policy_warnings(0x1_0000),
+            ["Guest policy reserved bit 17 is clear; firmware does not launch guests with such a policy"]
+        );
+    }
+
+    #[test]
+    fn key_and_vmpl_warnings() {
+        assert_eq!(
+            decode(SigningKey::None.to_key_info()).warnings,
+            ["Report is unsigned"]
+        );
+        assert_eq!(
+            decode(5 << 2).warnings,
+            ["KEY_INFO holds a reserved SIGNING_KEY value"]
+        );
+        assert_eq!(
+            decode(0b10).warnings,
+            ["MASK_CHIP_KEY is set: the report signature is zero and cannot be verified"]
+        );
+        let key_info = decode(0).key_info;
+        let policy = GuestPolicy::from_raw(0x3_0000);
+        assert!(warnings(&policy, &key_info, 3).is_empty());
+        assert_eq!(warnings(&policy, &key_info, 4), ["VMPL is out of range"]);
+    }
+}
@@ -0,0 +1,358 @@
+//! Just enough DER to build and pick apart AMD certificates.
+
+use anyhow::{bail, ensure};
+
+pub const TAG_BOOLEAN: u8 = 0x01;
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_UTF8_STRING: u8 = 0x0c;
+pub const TAG_UTC_TIME: u8 = 0x17;
+pub const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub const TAG_IA5_STRING: u8 = 0x16;
+pub const TAG_SEQUENCE: u8 = 0x30;
+
+/// Encode a single tag-length-value element.
+pub fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+    match value.len() {
+        len @ 0..=0x7f => der.push(len as u8),
+        len => {
+            let len = (len as u32).to_be_bytes();
+            let skip = len.iter().take_while(|b| **b == 0).count();
+            der.push(0x80 | (len.len() - skip) as u8);
+            der.extend_from_slice(&len[skip..]);
+        }
+    }
+    der.extend_from_slice(value);
+    der
+}
+
+/// Encode a small non-negative INTEGER.
+pub fn integer(value: u8) -> Vec<u8> {
+    unsigned_integer(&[value])
+}
+
+/// Encode a big-endian unsigned number, such as a serial, as an INTEGER.
+pub fn unsigned_integer(value: &[u8]) -> Vec<u8> {
+    let skip = value.iter().take_while(|b| **b == 0).count();
+    let value = &value[skip..];
+    // A leading zero keeps values with the top bit set positive.
+    match value.first() {
+        None => tlv(TAG_INTEGER, &[0]),
+        Some(first) if first & 0x80 != 0 => tlv(TAG_INTEGER, &[&[0], value].concat()),
+        Some(_) => tlv(TAG_INTEGER, value),
+    }
+}
+
+/// Encode seconds since the Unix epoch as the X.509 Time CHOICE: UTCTime
+/// through 2049, GeneralizedTime from 2050 on.
+pub fn time(unix: i64) -> Vec<u8> {
+    let days = unix.div_euclid(86400);
+    let secs = unix.rem_euclid(86400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let rest = format!(
+        "{month:02}{day:02}{:02}{:02}{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    );
+    if (1950..2050).contains(&year) {
+        tlv(TAG_UTC_TIME, format!("{:02}{rest}", year % 100).as_bytes())
+    } else {
+        tlv(TAG_GENERALIZED_TIME, format!("{year:04}{rest}").as_bytes())
+    }
+}
+
+/// One decoded element: its tag, its full encoding and its contents.
+#[derive(Clone, Copy, Debug)]
+pub struct Element<'a> {
+    pub tag: u8,
+    pub raw: &'a [u8],
+    pub value: &'a [u8],
+}
+
+/// Split the first element off `input`, returning it and the remaining bytes.
+pub fn read(input: &[u8]) -> anyhow::Result<(Element<'_>, &[u8])> {
+    ensure!(input.len() >= 2, "Truncated DER element");
+    let tag = input[0];
+    ensure!(tag & 0x1f != 0x1f, "Multi-byte DER tags are not supported");
+
+    let (len, header) = match input[1] {
+        len @ 0..=0x7f => (len as usize, 2),
+        0x80 => bail!("Indefinite DER lengths are not allowed"),
+        first => {
+            let count = (first & 0x7f) as usize;
+            ensure!(count <= 4, "DER length too large");
+            ensure!(input.len() >= 2 + count, "Truncated DER length");
+            let len = input[2..2 + count]
+                .iter()
+                .fold(0usize, |len, b| (len << 8) | *b as usize);
+            (len, 2 + count)
+        }
+    };
+
+    ensure!(input.len() - header >= len, "Truncated DER value");
+    let element = Element {
+        tag,
+        raw: &input[..header + len],
+        value: &input[header..header + len],
+    };
+    Ok((element, &input[header + len..]))
+}
+
+/// Read an element and check that it carries `tag`.
+pub fn expect(input: &[u8], tag: u8) -> anyhow::Result<(Element<'_>, &[u8])> {
+    let (element, rest) = read(input)?;
+    ensure!(
+        element.tag == tag,
+        "Expected DER tag {tag:#04x}, found {:#04x}",
+        element.tag
+    );
+    Ok((element, rest))
+}
+
+/// Decode a non-negative INTEGER that fits in a `u64`.
+pub fn read_u64(value: &[u8]) -> anyhow::Result<u64> {
+    ensure!(!value.is_empty(), "Empty INTEGER");
+    ensure!(value[0] & 0x80 == 0, "Negative INTEGER");
+    let value = match value {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        value => value,
+    };
+    ensure!(value.len() <= 8, "INTEGER out of range");
+    Ok(value.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+}
+
+/// Decode an OBJECT IDENTIFIER into dotted notation.
+pub fn oid_to_string(value: &[u8]) -> anyhow::Result<String> {
+    ensure!(!value.is_empty(), "Empty OBJECT IDENTIFIER");
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for (i, byte) in value.iter().enumerate() {
+        ensure!(arc >> 57 == 0, "OBJECT IDENTIFIER arc too large");
+        arc = (arc << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        } else if i == value.len() - 1 {
+            bail!("Truncated OBJECT IDENTIFIER");
+        }
+    }
+
+    let first = arcs[0];
+    let (a, b) = match first {
+        0..=39 => (0, first),
+        40..=79 => (1, first - 40),
+        _ => (2, first - 80),
+    };
+    let mut oid = format!("{a}.{b}");
+    for arc in &arcs[1..] {
+        oid.push_str(&format!(".{arc}"));
+    }
+    Ok(oid)
+}
+
+/// The extnID, critical flag and extnValue contents of every extension in a
+/// DER certificate.
+pub fn cert_extensions(cert: &[u8]) -> anyhow::Result<Vec<(String, bool, &[u8])>> {
+    let (cert, _) = expect(cert, TAG_SEQUENCE)?;
+    let (tbs, _) = expect(cert.value, TAG_SEQUENCE)?;
+
+    // Skip to the explicitly tagged [3] extensions, the last TBS field.
+    let mut fields = tbs.value;
+    let extensions = loop {
+        if fields.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (field, rest) = read(fields)?;
+        if field.tag == 0xa3 {
+            break field.value;
+        }
+        fields = rest;
+    };
+
+    let (extensions, _) = expect(extensions, TAG_SEQUENCE)?;
+    let mut remaining = extensions.value;
+    let mut out = Vec::new();
+    while !remaining.is_empty() {
+        let (extension, rest) = expect(remaining, TAG_SEQUENCE)?;
+        remaining = rest;
+
+        let (oid, rest) = expect(extension.value, TAG_OID)?;
+        let (critical, rest) = match read(rest)? {
+            (flag, rest) if flag.tag == TAG_BOOLEAN => (flag.value != [0], rest),
+            _ => (false, rest),
+        };
+        let (value, _) = expect(rest, TAG_OCTET_STRING)?;
+        out.push((oid_to_string(oid.value)?, critical, value.value));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A self-signed P-256 certificate with three standard and two AMD extensions:
+    ///
+    /// ```sh
+    /// openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+    ///     -subj /CN=der-test -days 36500 \
+    ///     -addext "basicConstraints=critical,CA:TRUE" \
+    ///     -addext "1.3.6.1.4.1.3704.1.3.1=DER:02:01:03" \
+    ///     -addext "1.3.6.1.4.1.3704.1.4=DER:04:04:de:ad:be:ef"
+    /// ```
+    const CERT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBpTCCAUugAwIBAgIUVIVnPXas3YTD4vz3tneQ2FBzsdUwCgYIKoZIzj0EAwIw
+EzERMA8GA1UEAwwIZGVyLXRlc3QwIBcNMjYxMDE4MDk0MjQyWhgPMjEyNjA5MjQw
+OTQyNDJaMBMxETAPBgNVBAMMCGRlci10ZXN0MFkwEwYHKoZIzj0CAQYIKoZIzj0D
+AQcDQgAEnqeEtlWLOKoN70lu4hGib7zZv4w0kD/cltfXPClmf7w6pMNSOrQc3I7x
+9KP4vKsIec6GFknXFN5iybhiOxg9cKN7MHkwHQYDVR0OBBYEFJuClgSk3NyvveRO
+Dz1GBqtt0zvUMB8GA1UdIwQYMBaAFJuClgSk3NyvveRODz1GBqtt0zvUMA8GA1Ud
+EwEB/wQFMAMBAf8wEQYKKwYBBAGceAEDAQQDAgEDMBMGCSsGAQQBnHgBBAQGBATe
+rb7vMAoGCCqGSM49BAMCA0gAMEUCIQDAiR0oEnjrtRurV6Br0ej1QeHnFgHZTU6g
+YfX+Mq+kMQIgKbB6mTbQEnepGogcqQ64U8sirUKx790uQdDz4JHoTMo=
+-----END CERTIFICATE-----
+";
+
+    fn cert_der() -> Vec<u8> {
+        openssl::x509::X509::from_pem(CERT.as_bytes())
+            .unwrap()
+            .to_der()
+            .unwrap()
+    }
+
+    #[test]
+    fn short_form_length() {
+        let (element, rest) = read(&[0x04, 0x02, 0xaa, 0xbb, 0xcc]).unwrap();
+        assert_eq!(element.tag, TAG_OCTET_STRING);
+        assert_eq!(element.raw, [0x04, 0x02, 0xaa, 0xbb]);
+        assert_eq!(element.value, [0xaa, 0xbb]);
+        assert_eq!(rest, [0xcc]);
+
+        let (element, rest) = read(&[0x05, 0x00]).unwrap();
+        assert!(element.value.is_empty() && rest.is_empty());
+        assert_eq!(tlv(0x04, &[0; 0x7f])[..2], [0x04, 0x7f]);
+    }
+
+    #[test]
+    fn long_form_length() {
+        for (len, header) in [
+            (0x80, &[0x04, 0x81, 0x80][..]),
+            (0xff, &[0x04, 0x81, 0xff]),
+            (0x1234, &[0x04, 0x82, 0x12, 0x34]),
+            (0x010000, &[0x04, 0x83, 0x01, 0x00, 0x00]),
+        ] {
+            let value = vec![0x5a; len];
+            let der = tlv(TAG_OCTET_STRING, &value);
+            assert_eq!(&der[..header.len()], header);
+            let (element, rest) = expect(&der, TAG_OCTET_STRING).unwrap();
+            assert_eq!(element.value, value);
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        for input in [
+            &[][..],
+            &[0x04],
+            &[0x04, 0x03, 0x01, 0x02],
+            &[0x04, 0x81],
+            &[0x04, 0x82, 0x01],
+            &[0x04, 0x82, 0x01, 0x00, 0x00],
+            &[0x04, 0x80, 0x00, 0x00],
+            &[0x04, 0x85, 0x01, 0x00, 0x00, 0x00, 0x00],
+            &[0x1f, 0x01, 0x00],
+        ] {
+            assert!(read(input).is_err(), "{input:02x?}");
+        }
+
+        let der = cert_der();
+        for len in 0..der.len() {
+            assert!(cert_extensions(&der[..len]).is_err(), "{len} bytes");
+        }
+        assert!(expect(&[0x02, 0x01, 0x00], TAG_SEQUENCE).is_err());
+    }
+
+    #[test]
+    fn oid_multi_byte_arcs() {
+        let oid = |bytes: &[u8]| oid_to_string(bytes).unwrap();
+        assert_eq!(oid(&[0x55, 0x1d, 0x13]), "2.5.29.19");
+        assert_eq!(
+            oid(&[0x2b, 0x06, 0x01, 0x04, 0x01, 0x9c, 0x78, 0x01, 0x03, 0x01]),
+            "1.3.6.1.4.1.3704.1.3.1"
+        );
+        assert_eq!(
+            oid(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a]),
+            "1.2.840.113549.1.1.10"
+        );
+        // The first subidentifier carries arcs beyond 2.39 in several bytes.
+        assert_eq!(oid(&[0x88, 0x37, 0x03]), "2.999.3");
+        assert_eq!(oid(&[0x81, 0x80, 0x80, 0x00]), "2.2097072");
+
+        assert!(oid_to_string(&[]).is_err());
+        assert!(oid_to_string(&[0x2b, 0x9c]).is_err());
+        assert!(oid_to_string(&[0xff; 10]).is_err());
+    }
+
+    #[test]
+    fn read_u64_values() {
+        assert_eq!(read_u64(&[0x00]).unwrap(), 0);
+        assert_eq!(read_u64(&[0x7f]).unwrap(), 0x7f);
+        assert_eq!(read_u64(&[0x00, 0x80]).unwrap(), 0x80);
+        assert_eq!(read_u64(&[0x01, 0x00]).unwrap(), 0x100);
+        let max = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(read_u64(&max).unwrap(), u64::MAX);
+
+        assert!(read_u64(&[]).is_err());
+        assert!(read_u64(&[0x80]).is_err());
+        assert!(read_u64(&[0x01, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn unsigned_integer_round_trip() {
+        for value in [0u64, 1, 0x7f, 0x80, 0x10001, u64::MAX] {
+            let der = unsigned_integer(&value.to_be_bytes());
+            let (element, _) = expect(&der, TAG_INTEGER).unwrap();
+            assert_eq!(read_u64(element.value).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn extensions_of_known_cert() {
+        let der = cert_der();
+        let extensions = cert_extensions(&der).unwrap();
+        let summary: Vec<(&str, bool)> = extensions
+            .iter()
+            .map(|(oid, critical, _)| (oid.as_str(), *critical))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("2.5.29.14", false),
+                ("2.5.29.35", false),
+                ("2.5.29.19", true),
+                ("1.3.6.1.4.1.3704.1.3.1", false),
+                ("1.3.6.1.4.1.3704.1.4", false),
+            ]
+        );
+        assert_eq!(extensions[2].2, [0x30, 0x03, 0x01, 0x01, 0xff]);
+        assert_eq!(extensions[3].2, [0x02, 0x01, 0x03]);
+        assert_eq!(extensions[4].2, [0x04, 0x04, 0xde, 0xad, 0xbe, 0xef]);
+    }
+}
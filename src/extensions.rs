@@ -0,0 +1,241 @@
+use anyhow::{bail, Context};
+use openssl::{
+    asn1::{Asn1Object, Asn1OctetString},
+    x509::{X509Extension, X509Ref},
+};
+
+use crate::{der, tcb::Tcb};
+
+/// AMD VCEK certificate extension OIDs, under 1.3.6.1.4.1.3704 (AMD).
+pub const OID_STRUCT_VERSION: &str = "1.3.6.1.4.1.3704.1.1";
+pub const OID_PRODUCT_NAME: &str = "1.3.6.1.4.1.3704.1.2";
+pub const OID_BL_SPL: &str = "1.3.6.1.4.1.3704.1.3.1";
+pub const OID_TEE_SPL: &str = "1.3.6.1.4.1.3704.1.3.2";
+pub const OID_SNP_SPL: &str = "1.3.6.1.4.1.3704.1.3.3";
+pub const OID_UCODE_SPL: &str = "1.3.6.1.4.1.3704.1.3.8";
+pub const OID_FMC_SPL: &str = "1.3.6.1.4.1.3704.1.3.9";
+pub const OID_HW_ID: &str = "1.3.6.1.4.1.3704.1.4";
+pub const OID_CSP_ID: &str = "1.3.6.1.4.1.3704.1.5";
+
+/// Prefix shared by every AMD extension OID.
+const AMD_OID_PREFIX: &str = "1.3.6.1.4.1.3704.";
+
+/// Chip ID lengths a hwID can have: 64 bytes, or 8 on Turin.
+const HW_ID_LENGTHS: [usize; 2] = [64, 8];
+
+/// Human-readable name of an AMD extension OID.
+fn oid_name(oid: &str) -> Option<&'static str> {
+    Some(match oid {
+        OID_STRUCT_VERSION => "structVersion",
+        OID_PRODUCT_NAME => "productName",
+        OID_BL_SPL => "blSPL",
+        OID_TEE_SPL => "teeSPL",
+        OID_SNP_SPL => "snpSPL",
+        OID_UCODE_SPL => "ucodeSPL",
+        OID_FMC_SPL => "fmcSPL",
+        OID_HW_ID => "hwID",
+        OID_CSP_ID => "cspID",
+        _ => return None,
+    })
+}
+
+/// Every AMD extension of `cert` as a (name, value) pair, in certificate
+/// order. Unknown AMD OIDs are listed by number with their raw value.
+pub fn describe(cert: &X509Ref) -> anyhow::Result<Vec<(String, String)>> {
+    let der = cert.to_der()?;
+    let extensions = der::cert_extensions(&der).context("Malformed certificate extensions")?;
+
+    let mut out = Vec::new();
+    for (oid, _, value) in extensions {
+        if !oid.starts_with(AMD_OID_PREFIX) {
+            continue;
+        }
+        let name = oid_name(&oid).map_or_else(|| oid.clone(), str::to_string);
+        let decoded = match der::read(value) {
+            Ok((element, [])) => match element.tag {
+                der::TAG_INTEGER => der::read_u64(element.value).map(|v| v.to_string()).ok(),
+                der::TAG_IA5_STRING | der::TAG_UTF8_STRING => {
+                    String::from_utf8(element.value.to_vec()).ok()
+                }
+                der::TAG_OCTET_STRING => Some(hex::encode(element.value)),
+                _ => None,
+            },
+            _ => None,
+        };
+        out.push((name, decoded.unwrap_or_else(|| hex::encode(value))));
+    }
+    Ok(out)
+}
+
+/// The AMD-specific fields carried by a VCEK or VLEK certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcekExtensions {
+    pub product_name: String,
+    pub bl_spl: u8,
+    pub tee_spl: u8,
+    pub snp_spl: u8,
+    pub ucode_spl: u8,
+    /// Only present on Turin and later.
+    pub fmc_spl: Option<u8>,
+    /// Only present on VCEKs.
+    pub hw_id: Option<Vec<u8>>,
+    /// Only present on VLEKs: the cloud service provider the key was issued to.
+    pub csp_id: Option<String>,
+}
+
+impl VcekExtensions {
+    /// Encode the fields as non-critical X.509 extensions, as KDS does.
+    pub fn to_x509(&self) -> anyhow::Result<Vec<X509Extension>> {
+        let mut values = vec![
+            (OID_STRUCT_VERSION, der::integer(1)),
+            (
+                OID_PRODUCT_NAME,
+                der::tlv(der::TAG_IA5_STRING, self.product_name.as_bytes()),
+            ),
+            (OID_BL_SPL, der::integer(self.bl_spl)),
+            (OID_TEE_SPL, der::integer(self.tee_spl)),
+            (OID_SNP_SPL, der::integer(self.snp_spl)),
+            (OID_UCODE_SPL, der::integer(self.ucode_spl)),
+        ];
+        if let Some(fmc_spl) = self.fmc_spl {
+            values.push((OID_FMC_SPL, der::integer(fmc_spl)));
+        }
+        if let Some(hw_id) = &self.hw_id {
+            values.push((OID_HW_ID, der::tlv(der::TAG_OCTET_STRING, hw_id)));
+        }
+        if let Some(csp_id) = &self.csp_id {
+            values.push((
+                OID_CSP_ID,
+                der::tlv(der::TAG_UTF8_STRING, csp_id.as_bytes()),
+            ));
+        }
+
+        values
+            .into_iter()
+            .map(|(oid, der)| {
+                let oid = Asn1Object::from_str(oid)?;
+                let value = Asn1OctetString::new_from_bytes(&der)?;
+                X509Extension::new_from_der(&oid, false, &value)
+            })
+            .collect::<Result<_, _>>()
+            .context("Failed to encode VCEK extensions")
+    }
+
+    /// Decode the AMD extensions of a VCEK or VLEK certificate.
+    pub fn from_cert(cert: &X509Ref) -> anyhow::Result<Self> {
+        let der = cert.to_der()?;
+        let extensions = der::cert_extensions(&der).context("Malformed certificate extensions")?;
+        let find = |oid: &str| {
+            extensions
+                .iter()
+                .find(|(id, _, _)| id == oid)
+                .map(|(_, _, value)| *value)
+        };
+        let spl = |oid: &str, name: &str| -> anyhow::Result<Option<u8>> {
+            let Some(value) = find(oid) else {
+                return Ok(None);
+            };
+            let (integer, _) = der::expect(value, der::TAG_INTEGER)?;
+            let spl = der::read_u64(integer.value)?;
+            let spl = u8::try_from(spl).with_context(|| format!("{name} {spl} out of range"))?;
+            Ok(Some(spl))
+        };
+        let required = |oid: &str, name: &str| -> anyhow::Result<u8> {
+            spl(oid, name)?.with_context(|| format!("Certificate has no {name} extension"))
+        };
+
+        let product_name =
+            find(OID_PRODUCT_NAME).context("Certificate has no productName extension")?;
+        let (product_name, _) = der::expect(product_name, der::TAG_IA5_STRING)?;
+        let product_name =
+            String::from_utf8(product_name.value.to_vec()).context("productName is not ASCII")?;
+
+        let hw_id = find(OID_HW_ID).map(decode_hw_id).transpose()?;
+        let csp_id = match find(OID_CSP_ID) {
+            Some(csp_id) => {
+                let (csp_id, _) = der::read(csp_id)?;
+                Some(String::from_utf8(csp_id.value.to_vec()).context("cspID is not UTF-8")?)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            product_name,
+            bl_spl: required(OID_BL_SPL, "blSPL")?,
+            tee_spl: required(OID_TEE_SPL, "teeSPL")?,
+            snp_spl: required(OID_SNP_SPL, "snpSPL")?,
+            ucode_spl: required(OID_UCODE_SPL, "ucodeSPL")?,
+            fmc_spl: spl(OID_FMC_SPL, "fmcSPL")?,
+            hw_id,
+            csp_id,
+        })
+    }
+
+    /// The TCB this key was derived for.
+    pub fn tcb(&self) -> Tcb {
+        Tcb {
+            fmc: self.fmc_spl,
+            bootloader: self.bl_spl,
+            tee: self.tee_spl,
+            snp: self.snp_spl,
+            microcode: self.ucode_spl,
+        }
+    }
+}
+
+/// The chip ID in a hwID extension value: either the raw bytes or an OCTET
+/// STRING holding them. The two never have the same length, so the length
+/// alone decides which it is.
+fn decode_hw_id(value: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if HW_ID_LENGTHS.contains(&value.len()) {
+        return Ok(value.to_vec());
+    }
+    match der::expect(value, der::TAG_OCTET_STRING) {
+        Ok((inner, [])) if HW_ID_LENGTHS.contains(&inner.value.len()) => Ok(inner.value.to_vec()),
+        _ => bail!("hwID is {} bytes, not a 64 or 8 byte chip ID", value.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hw_id_by_length() {
+        let chip = [0xab; 64];
+        assert_eq!(decode_hw_id(&chip).unwrap(), chip);
+        let wrapped = der::tlv(der::TAG_OCTET_STRING, &chip);
+        assert_eq!(decode_hw_id(&wrapped).unwrap(), chip);
+
+        let turin = [0xcd; 8];
+        assert_eq!(decode_hw_id(&turin).unwrap(), turin);
+        let wrapped = der::tlv(der::TAG_OCTET_STRING, &turin);
+        assert_eq!(decode_hw_id(&wrapped).unwrap(), turin);
+    }
+
+    #[test]
+    fn raw_hw_id_that_looks_wrapped() {
+        // A raw chip ID beginning like a 62-byte OCTET STRING stays whole.
+        let mut chip = [0x11; 64];
+        chip[..2].copy_from_slice(&[der::TAG_OCTET_STRING, 62]);
+        assert_eq!(decode_hw_id(&chip).unwrap(), chip);
+
+        let mut turin = [0x22; 8];
+        turin[..2].copy_from_slice(&[der::TAG_OCTET_STRING, 6]);
+        assert_eq!(decode_hw_id(&turin).unwrap(), turin);
+    }
+
+    #[test]
+    fn hw_id_of_other_length() {
+        for value in [
+            vec![0; 32],
+            der::tlv(der::TAG_OCTET_STRING, &[0; 32]),
+            der::tlv(der::TAG_OCTET_STRING, &[0; 63]),
+            [der::tlv(der::TAG_OCTET_STRING, &[0; 8]), vec![0]].concat(),
+            Vec::new(),
+        ] {
+            let err = decode_hw_id(&value).unwrap_err();
+            assert!(err.to_string().starts_with("hwID is"), "{err}");
+        }
+    }
+}
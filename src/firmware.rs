@@ -0,0 +1,176 @@
+use anyhow::{bail, Context};
+use clap::{Args, ValueEnum};
+use sev::firmware::{
+    guest::{AttestationReport, DerivedKey, Firmware},
+    host::CertTableEntry,
+};
+use std::{fs, path::PathBuf};
+
+use crate::{
+    report,
+    sim::{self, SimConfig, SimulatedFirmware},
+};
+
+/// Source of attestation reports, mirroring the `/dev/sev-guest` interface.
+pub trait ReportProvider {
+    fn get_report(
+        &mut self,
+        message_version: Option<u8>,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<AttestationReport>;
+
+    fn get_ext_report(
+        &mut self,
+        message_version: Option<u8>,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<(AttestationReport, Vec<CertTableEntry>)>;
+
+    fn get_derived_key(
+        &mut self,
+        message_version: Option<u8>,
+        request: DerivedKey,
+    ) -> anyhow::Result<[u8; 32]>;
+}
+
+impl ReportProvider for Firmware {
+    fn get_report(
+        &mut self,
+        message_version: Option<u8>,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<AttestationReport> {
+        Ok(Firmware::get_report(self, message_version, data, vmpl)?)
+    }
+
+    fn get_ext_report(
+        &mut self,
+        message_version: Option<u8>,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<(AttestationReport, Vec<CertTableEntry>)> {
+        Ok(Firmware::get_ext_report(self, message_version, data, vmpl)?)
+    }
+
+    fn get_derived_key(
+        &mut self,
+        message_version: Option<u8>,
+        request: DerivedKey,
+    ) -> anyhow::Result<[u8; 32]> {
+        Ok(Firmware::get_derived_key(self, message_version, request)?)
+    }
+}
+
+/// Replays a previously captured raw `AttestationReport` binary.
+pub struct FileProvider {
+    path: PathBuf,
+}
+
+impl FileProvider {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn load(&self) -> anyhow::Result<AttestationReport> {
+        let bytes = fs::read(&self.path)
+            .with_context(|| format!("Failed to read report file {}", self.path.display()))?;
+        report::from_bytes(&bytes)
+            .with_context(|| format!("Invalid report file {}", self.path.display()))
+    }
+}
+
+impl ReportProvider for FileProvider {
+    fn get_report(
+        &mut self,
+        _message_version: Option<u8>,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<AttestationReport> {
+        let report = self.load()?;
+
+        if data.is_some_and(|data| data != report.report_data) {
+            log::warn!("Replayed report does not contain the requested report data");
+        }
+        if vmpl.is_some_and(|vmpl| vmpl != report.vmpl) {
+            log::warn!("Replayed report was generated at VMPL {}", report.vmpl);
+        }
+
+        Ok(report)
+    }
+
+    fn get_ext_report(
+        &mut self,
+        message_version: Option<u8>,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<(AttestationReport, Vec<CertTableEntry>)> {
+        let report = self.get_report(message_version, data, vmpl)?;
+        Ok((report, Vec::new()))
+    }
+
+    fn get_derived_key(
+        &mut self,
+        _message_version: Option<u8>,
+        _request: DerivedKey,
+    ) -> anyhow::Result<[u8; 32]> {
+        bail!("Derived keys are not available from a replayed report")
+    }
+}
+
+/// Report provider selectable from the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// The SEV-SNP guest device at /dev/sev-guest.
+    Hardware,
+    /// A captured raw report, see --report-file.
+    File,
+    /// Synthetic reports signed by a test VCEK, see --sim-config and --sim-key.
+    Sim,
+}
+
+#[derive(Args)]
+pub struct BackendArgs {
+    /// Where attestation reports come from.
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Hardware)]
+    pub backend: Backend,
+
+    /// Raw attestation report replayed by the file backend.
+    #[arg(long, global = true)]
+    pub report_file: Option<PathBuf>,
+
+    /// JSON file with the report fields produced by the sim backend.
+    #[arg(long, global = true)]
+    pub sim_config: Option<PathBuf>,
+
+    /// PEM P-384 private key the sim backend signs reports with.
+    #[arg(long, global = true)]
+    pub sim_key: Option<PathBuf>,
+}
+
+pub fn open(args: &BackendArgs) -> anyhow::Result<Box<dyn ReportProvider>> {
+    match args.backend {
+        Backend::Hardware => {
+            let fw = Firmware::open().context("Failed to open firmware")?;
+            log::info!("Opened firmware interface");
+            Ok(Box::new(fw))
+        }
+        Backend::File => {
+            let path = args
+                .report_file
+                .clone()
+                .context("The file backend requires --report-file")?;
+            log::info!("Replaying report from {}", path.display());
+            Ok(Box::new(FileProvider::new(path)))
+        }
+        Backend::Sim => {
+            let config = match &args.sim_config {
+                Some(path) => SimConfig::from_file(path)?,
+                None => SimConfig::default(),
+            };
+            let vcek = sim::load_vcek_key(args.sim_key.as_deref())?;
+            log::info!("Using simulated SNP firmware");
+            Ok(Box::new(SimulatedFirmware::new(config, vcek)))
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! SNP launch measurement of IGVM images, e.g. COCONUT-SVSM.
+//!
+//! The loader replays the SEV-SNP directives in file order: page data and
+//! parameter areas become SNP_LAUNCH_UPDATE pages, and VP contexts become
+//! VMSA pages. KVM measures every VMSA at one fixed GPA, other hypervisors at
+//! the GPA the file declares. An SNP ID block and guest policy, if present,
+//! determine further report fields, which are predicted here as well.
+
+use ::igvm::{IgvmDirectiveHeader, IgvmFile, IgvmInitializationHeader, IgvmPlatformHeader};
+use anyhow::{bail, ensure, Context};
+use clap::ValueEnum;
+use igvm_defs::{IgvmPageDataType, IgvmPlatformType, IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY};
+use openssl::sha::sha384;
+use std::{collections::HashMap, fmt, fs, path::Path};
+use zerocopy::IntoBytes;
+
+use crate::{
+    measure::{LaunchDigest, PageType, VMSA_GPA},
+    vmsa::PAGE_SIZE,
+};
+
+/// Size of the ID and author key fields of SNP_LAUNCH_FINISH's ID_AUTH_INFO.
+const ID_KEY_SIZE: usize = 0x404;
+
+/// The hypervisor loading an IGVM image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Platform {
+    /// QEMU/KVM: every VMSA is measured at 0xFFFFFFFFF000.
+    Kvm,
+    /// Hyper-V: each VMSA is measured at its VP context's GPA.
+    HyperV,
+}
+
+/// The report fields an IGVM image determines.
+pub struct IgvmMeasurement {
+    pub measurement: [u8; 48],
+    pub policy: Option<u64>,
+    pub id_block: Option<IdBlock>,
+}
+
+/// The SNP ID block the loader passes to SNP_LAUNCH_FINISH.
+pub struct IdBlock {
+    /// The launch digest the block was signed for.
+    pub ld: [u8; 48],
+    pub family_id: [u8; 16],
+    pub image_id: [u8; 16],
+    pub guest_svn: u32,
+    pub id_key_digest: [u8; 48],
+    /// All zero unless the author key is enabled.
+    pub author_key_digest: [u8; 48],
+}
+
+impl IgvmMeasurement {
+    pub fn load(path: &Path, platform: Platform) -> anyhow::Result<Self> {
+        let data =
+            fs::read(path).with_context(|| format!("Failed to read IGVM {}", path.display()))?;
+        let file = IgvmFile::new_from_binary(&data, Some(::igvm::IsolationType::Snp))
+            .with_context(|| format!("Invalid IGVM file {}", path.display()))?;
+        Self::from_file(&file, platform)
+            .with_context(|| format!("Cannot measure {}", path.display()))
+    }
+
+    fn from_file(file: &IgvmFile, platform: Platform) -> anyhow::Result<Self> {
+        let mask = file
+            .platforms()
+            .iter()
+            .find_map(|IgvmPlatformHeader::SupportedPlatform(platform)| {
+                (platform.platform_type == IgvmPlatformType::SEV_SNP)
+                    .then_some(platform.compatibility_mask)
+            })
+            .context("IGVM file does not support SEV-SNP")?;
+        let applies = |header_mask: u32| header_mask & mask != 0;
+
+        let policy = file
+            .initializations()
+            .iter()
+            .find_map(|header| match header {
+                IgvmInitializationHeader::GuestPolicy {
+                    policy,
+                    compatibility_mask,
+                } if applies(*compatibility_mask) => Some(*policy),
+                _ => None,
+            });
+
+        let mut digest = LaunchDigest::new();
+        let mut parameter_areas = HashMap::new();
+        let mut id_block = None;
+        for header in file.directives() {
+            if !header.compatibility_mask().is_none_or(applies) {
+                continue;
+            }
+            match header {
+                IgvmDirectiveHeader::PageData {
+                    gpa,
+                    flags,
+                    data_type,
+                    data,
+                    ..
+                } => {
+                    if flags.shared() {
+                        continue;
+                    }
+                    ensure!(
+                        !flags.is_2mb_page(),
+                        "2 MB page data at {gpa:#x} is not supported"
+                    );
+                    ensure!(
+                        data.len() <= PAGE_SIZE,
+                        "page data at {gpa:#x} is {} bytes, more than a page",
+                        data.len()
+                    );
+                    match *data_type {
+                        IgvmPageDataType::SECRETS => {
+                            digest.empty_pages(PageType::Secrets, *gpa, PAGE_SIZE as u64)
+                        }
+                        IgvmPageDataType::CPUID_DATA | IgvmPageDataType::CPUID_XF => {
+                            digest.empty_pages(PageType::Cpuid, *gpa, PAGE_SIZE as u64)
+                        }
+                        _ if flags.unmeasured() => {
+                            digest.empty_pages(PageType::Unmeasured, *gpa, PAGE_SIZE as u64)
+                        }
+                        // Pages without data are zero-filled, but still measured.
+                        _ if data.is_empty() => digest.normal_pages(*gpa, &[0; PAGE_SIZE]),
+                        _ => digest.normal_pages(*gpa, data),
+                    }
+                }
+                IgvmDirectiveHeader::ParameterArea {
+                    number_of_bytes,
+                    parameter_area_index,
+                    ..
+                } => {
+                    parameter_areas.insert(*parameter_area_index, *number_of_bytes);
+                }
+                IgvmDirectiveHeader::ParameterInsert(insert) => {
+                    let size = parameter_areas
+                        .get(&insert.parameter_area_index)
+                        .with_context(|| {
+                            format!(
+                                "parameter area {} is inserted but never declared",
+                                insert.parameter_area_index
+                            )
+                        })?;
+                    digest.empty_pages(PageType::Unmeasured, insert.gpa, *size);
+                }
+                IgvmDirectiveHeader::SnpVpContext { gpa, vmsa, .. } => {
+                    let mut page = [0u8; PAGE_SIZE];
+                    let bytes = vmsa.as_bytes();
+                    page[..bytes.len()].copy_from_slice(bytes);
+                    let gpa = match platform {
+                        Platform::Kvm => VMSA_GPA,
+                        Platform::HyperV => *gpa,
+                    };
+                    digest.vmsa_page(gpa, &page);
+                }
+                IgvmDirectiveHeader::SnpIdBlock {
+                    author_key_enabled,
+                    ld,
+                    family_id,
+                    image_id,
+                    guest_svn,
+                    id_public_key,
+                    author_public_key,
+                    ..
+                } => {
+                    if id_block.is_some() {
+                        bail!("IGVM file has more than one SNP ID block");
+                    }
+                    id_block = Some(IdBlock {
+                        ld: *ld,
+                        family_id: *family_id,
+                        image_id: *image_id,
+                        guest_svn: *guest_svn,
+                        id_key_digest: key_digest(id_public_key),
+                        author_key_digest: match author_key_enabled {
+                            0 => [0; 48],
+                            _ => key_digest(author_public_key),
+                        },
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let measurement = digest.digest();
+        if let Some(id_block) = &id_block {
+            if id_block.ld != measurement {
+                log::warn!(
+                    "The ID block was signed for launch digest {}, the firmware will refuse to launch this image",
+                    hex::encode(id_block.ld)
+                );
+            }
+        }
+        Ok(Self {
+            measurement,
+            policy,
+            id_block,
+        })
+    }
+}
+
+/// The digest the firmware reports for a key: SHA-384 over the key as the
+/// loader copies it into its zero-filled ID_AUTH_INFO field.
+fn key_digest(key: &IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY) -> [u8; 48] {
+    let mut field = [0u8; ID_KEY_SIZE];
+    let bytes = key.as_bytes();
+    field[..bytes.len()].copy_from_slice(bytes);
+    sha384(&field)
+}
+
+impl fmt::Display for IgvmMeasurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = |f: &mut fmt::Formatter<'_>, label: &str, value: &dyn fmt::Display| {
+            writeln!(f, "{:<20} {value}", format!("{label}:"))
+        };
+        writeln!(f, "{}", hex::encode(self.measurement))?;
+        if let Some(policy) = self.policy {
+            line(f, "Policy", &format!("{policy:#x}"))?;
+        }
+        if let Some(id_block) = &self.id_block {
+            line(f, "Family ID", &hex::encode(id_block.family_id))?;
+            line(f, "Image ID", &hex::encode(id_block.image_id))?;
+            line(f, "Guest SVN", &id_block.guest_svn)?;
+            line(f, "ID key digest", &hex::encode(id_block.id_key_digest))?;
+            line(
+                f,
+                "Author key digest",
+                &hex::encode(id_block.author_key_digest),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::igvm::{snp_defs::SevVmsa, IgvmRevision};
+    use igvm_defs::{
+        IgvmPageDataFlags, IGVM_VHS_PARAMETER, IGVM_VHS_PARAMETER_INSERT,
+        IGVM_VHS_SNP_ID_BLOCK_SIGNATURE, IGVM_VHS_SUPPORTED_PLATFORM,
+    };
+    use zerocopy::FromZeros;
+
+    fn public_key(qx: u8, qy: u8) -> Box<IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY> {
+        let mut key = IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY::new_zeroed();
+        key.curve = 2;
+        key.qx = [qx; 72];
+        key.qy = [qy; 72];
+        Box::new(key)
+    }
+
+    /// One page, one parameter area, a VP context at 0xf000 rather than
+    /// KVM's VMSA GPA, and an ID block.
+    fn test_file(author_key_enabled: u8) -> IgvmFile {
+        let platform = IgvmPlatformHeader::SupportedPlatform(IGVM_VHS_SUPPORTED_PLATFORM {
+            compatibility_mask: 1,
+            highest_vtl: 0,
+            platform_type: IgvmPlatformType::SEV_SNP,
+            platform_version: 1,
+            shared_gpa_boundary: 0,
+        });
+        let policy = IgvmInitializationHeader::GuestPolicy {
+            policy: 0x30000,
+            compatibility_mask: 1,
+        };
+        let mut vmsa = SevVmsa::new_zeroed();
+        vmsa.rip = 0xfff0;
+        vmsa.cr0 = 0x10;
+        vmsa.sev_features = 1u64.into();
+        let directives = vec![
+            IgvmDirectiveHeader::PageData {
+                gpa: 0x1000,
+                compatibility_mask: 1,
+                flags: IgvmPageDataFlags::new(),
+                data_type: IgvmPageDataType::NORMAL,
+                data: (0..PAGE_SIZE).map(|i| i as u8).collect(),
+            },
+            IgvmDirectiveHeader::ParameterArea {
+                number_of_bytes: 0x2000,
+                parameter_area_index: 0,
+                initial_data: Vec::new(),
+            },
+            IgvmDirectiveHeader::MemoryMap(IGVM_VHS_PARAMETER {
+                parameter_area_index: 0,
+                byte_offset: 0,
+            }),
+            IgvmDirectiveHeader::ParameterInsert(IGVM_VHS_PARAMETER_INSERT {
+                gpa: 0x8000,
+                compatibility_mask: 1,
+                parameter_area_index: 0,
+            }),
+            IgvmDirectiveHeader::SnpVpContext {
+                gpa: 0xf000,
+                compatibility_mask: 1,
+                vp_index: 0,
+                vmsa: Box::new(vmsa),
+            },
+            IgvmDirectiveHeader::SnpIdBlock {
+                compatibility_mask: 1,
+                author_key_enabled,
+                reserved: [0; 3],
+                ld: [0; 48],
+                family_id: [0xfa; 16],
+                image_id: [0x1d; 16],
+                version: 1,
+                guest_svn: 7,
+                id_key_algorithm: 1,
+                author_key_algorithm: 1,
+                id_key_signature: Box::new(IGVM_VHS_SNP_ID_BLOCK_SIGNATURE::new_zeroed()),
+                id_public_key: public_key(0x11, 0x22),
+                author_key_signature: Box::new(IGVM_VHS_SNP_ID_BLOCK_SIGNATURE::new_zeroed()),
+                author_public_key: public_key(0x33, 0),
+            },
+        ];
+        IgvmFile::new(IgvmRevision::V1, vec![platform], vec![policy], directives).unwrap()
+    }
+
+    #[test]
+    fn vmsa_gpa_by_platform() {
+        // Both values agree with igvm::measurement::generate_snp_measurement,
+        // which measures VMSAs at their declared GPA: as is for Hyper-V, and
+        // with the VP context moved to 0xFFFFFFFFF000 for KVM.
+        let file = test_file(1);
+        let kvm = IgvmMeasurement::from_file(&file, Platform::Kvm).unwrap();
+        assert_eq!(
+            hex::encode(kvm.measurement),
+            "cc9a80e8f3ce9db4f38516580ef5d8bee13fd4bc6398c8784693cffdc6dcaa05f21ed0c95a838bdb86f191dccd72a5a1"
+        );
+        let hyper_v = IgvmMeasurement::from_file(&file, Platform::HyperV).unwrap();
+        assert_eq!(
+            hex::encode(hyper_v.measurement),
+            "7a995628ea57345e791d5f346d9a994804ab6ce3b123d4ca5d531fa89bb0a233814fac406fc4a6a4ef96ad8c15f625bc"
+        );
+    }
+
+    #[test]
+    fn id_block_fields() {
+        let measurement = IgvmMeasurement::from_file(&test_file(1), Platform::Kvm).unwrap();
+        assert_eq!(measurement.policy, Some(0x30000));
+        let id_block = measurement.id_block.unwrap();
+        assert_eq!(id_block.ld, [0; 48]);
+        assert_eq!(id_block.family_id, [0xfa; 16]);
+        assert_eq!(id_block.image_id, [0x1d; 16]);
+        assert_eq!(id_block.guest_svn, 7);
+        // SHA-384 of the 152-byte key zero-padded to 0x404 bytes.
+        assert_eq!(
+            hex::encode(id_block.id_key_digest),
+            "652690091c67886882b9d8e187c653ac9fda6a5928a968014b7c9215818c429299b8fc1a86b4c280a0ab0ebbff2cd231"
+        );
+        assert_eq!(
+            hex::encode(id_block.author_key_digest),
+            "1053d8997dfd359d079fb7a894eeff29c76e111576358e94fb6a56526a21e1e80cd5d6d8875f1107f4f7cc03500c06a9"
+        );
+    }
+
+    #[test]
+    fn author_key_disabled() {
+        let measurement = IgvmMeasurement::from_file(&test_file(0), Platform::Kvm).unwrap();
+        assert_eq!(measurement.id_block.unwrap().author_key_digest, [0; 48]);
+    }
+}
@@ -0,0 +1,422 @@
+//! Client for the AMD Key Distribution Service.
+
+use anyhow::Context;
+use clap::Args;
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use std::{
+    fmt, fs,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use crate::{certs, crl, product::Product, report::SigningKey, tcb::Tcb};
+
+pub const AMD_KDS_URL: &str = "https://kdsintf.amd.com";
+
+/// First retry delay; doubled on every further attempt.
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+/// Upper bound on any single wait, including server-requested ones.
+const BACKOFF_MAX: Duration = Duration::from_secs(120);
+
+#[derive(Args)]
+pub struct KdsArgs {
+    /// Base URL of the key distribution service, e.g. a local mock-kds.
+    #[arg(long, global = true, default_value = AMD_KDS_URL)]
+    pub kds_url: String,
+
+    /// HTTP(S) proxy for KDS requests; the usual *_PROXY variables are honoured otherwise.
+    #[arg(long, global = true)]
+    pub kds_proxy: Option<String>,
+
+    /// Extra PEM root certificate to trust for the KDS TLS connection.
+    #[arg(long, global = true)]
+    pub kds_ca_cert: Option<PathBuf>,
+
+    /// Per-request timeout in seconds.
+    #[arg(long, global = true, default_value_t = 30)]
+    pub kds_timeout: u64,
+
+    /// How many times to retry rate-limited or failed requests.
+    #[arg(long, global = true, default_value_t = 5)]
+    pub kds_retries: u32,
+}
+
+/// Failures a caller may want to tell apart.
+#[derive(Debug)]
+pub enum KdsError {
+    /// KDS does not know this hardware ID.
+    UnknownChip { product: Product, hw_id: String },
+    /// KDS rejected the requested TCB.
+    BadTcb { tcb: Tcb, message: String },
+    /// Still rate limited after all retries.
+    RateLimited { retry_after: Option<Duration> },
+    /// Any other unexpected HTTP status.
+    Status { status: StatusCode, message: String },
+}
+
+impl fmt::Display for KdsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownChip { product, hw_id } => {
+                write!(f, "KDS has no {product} VCEK for chip {hw_id}")
+            }
+            Self::BadTcb { tcb, message } => {
+                write!(f, "KDS rejected TCB {tcb}: {message}")
+            }
+            Self::RateLimited {
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "KDS rate limited the request (Retry-After {}s)",
+                retry_after.as_secs()
+            ),
+            Self::RateLimited { retry_after: None } => {
+                write!(f, "KDS rate limited the request")
+            }
+            Self::Status { status, message } => write!(f, "KDS returned {status}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for KdsError {}
+
+pub struct KdsClient {
+    base_url: String,
+    client: reqwest::Client,
+    retries: u32,
+}
+
+impl KdsClient {
+    pub fn new(args: &KdsArgs) -> anyhow::Result<Self> {
+        let timeout = Duration::from_secs(args.kds_timeout);
+        let mut builder = reqwest::Client::builder()
+            .timeout(timeout)
+            .connect_timeout(timeout)
+            .user_agent(concat!("sev-tool/", env!("CARGO_PKG_VERSION")));
+
+        if let Some(proxy) = &args.kds_proxy {
+            let proxy =
+                reqwest::Proxy::all(proxy).with_context(|| format!("Invalid KDS proxy {proxy}"))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = &args.kds_ca_cert {
+            let pem = fs::read(path)
+                .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+            let cert = reqwest::Certificate::from_pem(&pem)
+                .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+            builder = builder.add_root_certificate(cert);
+        }
+
+        Ok(Self {
+            base_url: args.kds_url.trim_end_matches('/').to_string(),
+            client: builder.build().context("Failed to create HTTP client")?,
+            retries: args.kds_retries,
+        })
+    }
+
+    pub async fn vcek(&self, product: Product, hw_id: &[u8], tcb: Tcb) -> anyhow::Result<Vec<u8>> {
+        let hw_id = hex::encode(hw_id);
+        let url = format!(
+            "{}/vcek/v1/{product}/{hw_id}?{}",
+            self.base_url,
+            tcb.kds_query()
+        );
+
+        let response = self.get(&url).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Err(KdsError::UnknownChip { product, hw_id }.into()),
+            StatusCode::BAD_REQUEST => {
+                let message = body_text(response).await;
+                Err(KdsError::BadTcb { tcb, message }.into())
+            }
+            _ => {
+                let vcek = body(response).await?;
+                certs::parse_cert(&vcek).context("KDS response is not an X.509 certificate")?;
+                Ok(vcek)
+            }
+        }
+    }
+
+    /// The ASK (or, for VLEKs, ASVK) and ARK for `product`.
+    pub async fn cert_chain(&self, product: Product, key: SigningKey) -> anyhow::Result<Vec<u8>> {
+        let url = self.product_url(product, key, "cert_chain");
+        let response = self.get(&url).await?;
+        let chain = body(response).await?;
+        certs::split_cert_chain(&chain)
+            .with_context(|| format!("KDS response is not a valid {product} certificate chain"))?;
+        Ok(chain)
+    }
+
+    pub async fn crl(&self, product: Product, key: SigningKey) -> anyhow::Result<Vec<u8>> {
+        let url = self.product_url(product, key, "crl");
+        let response = self.get(&url).await?;
+        let crl = body(response).await?;
+        crl::parse(&crl).context("KDS response is not a valid CRL")?;
+        Ok(crl)
+    }
+
+    fn product_url(&self, product: Product, key: SigningKey, resource: &str) -> String {
+        format!(
+            "{}/{}/v1/{product}/{resource}",
+            self.base_url,
+            key.kds_service()
+        )
+    }
+
+    /// GET `url`, retrying rate limits, server errors and transport failures
+    /// with exponential backoff. Client errors are returned to the caller.
+    async fn get(&self, url: &str) -> anyhow::Result<Response> {
+        log::info!("Requesting {url}");
+        let mut attempt = 0;
+        loop {
+            let (delay, error) = match self.client.get(url).send().await {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let retry_after = retry_after(&response);
+                    let error = KdsError::RateLimited { retry_after };
+                    (retry_after, anyhow::Error::new(error))
+                }
+                Ok(response) if response.status().is_server_error() => {
+                    let status = response.status();
+                    let message = body_text(response).await;
+                    (None, KdsError::Status { status, message }.into())
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => (
+                    None,
+                    anyhow::Error::new(e).context(format!("Failed to get {url}")),
+                ),
+                Err(e) => return Err(e).with_context(|| format!("Failed to get {url}")),
+            };
+
+            if attempt >= self.retries {
+                return Err(error);
+            }
+            let delay = delay.unwrap_or_else(|| backoff(attempt)).min(BACKOFF_MAX);
+            attempt += 1;
+            log::warn!(
+                "{error:#}; retry {attempt}/{} in {:.1}s",
+                self.retries,
+                delay.as_secs_f32()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+async fn body(response: Response) -> anyhow::Result<Vec<u8>> {
+    let status = response.status();
+    if !status.is_success() {
+        let message = body_text(response).await;
+        return Err(KdsError::Status { status, message }.into());
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// The first line of an error body, for messages.
+async fn body_text(response: Response) -> String {
+    let text = response.text().await.unwrap_or_default();
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty());
+    line.unwrap_or("(empty body)").chars().take(200).collect()
+}
+
+/// Parse `Retry-After` as either delay-seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Exponential backoff with jitter: a random delay between half and all of
+/// `BACKOFF_BASE * 2^attempt`.
+fn backoff(attempt: u32) -> Duration {
+    let delay = BACKOFF_BASE
+        .saturating_mul(1 << attempt.min(16))
+        .min(BACKOFF_MAX);
+    let mut random = [0u8; 4];
+    let jitter = match openssl::rand::rand_bytes(&mut random) {
+        Ok(()) => u32::from_le_bytes(random) as f64 / u32::MAX as f64,
+        Err(_) => 0.5,
+    };
+    delay.mul_f64(0.5 + jitter / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extensions::VcekExtensions,
+        mock_kds::{self, MockKdsArgs},
+        pki::TestCa,
+        testutil::TempDir,
+    };
+    use std::sync::OnceLock;
+
+    const CHIP: [u8; 64] = [0x42; 64];
+    const TCB: Tcb = Tcb {
+        fmc: None,
+        bootloader: 3,
+        tee: 0,
+        snp: 22,
+        microcode: 210,
+    };
+
+    fn ca() -> &'static TestCa {
+        static CA: OnceLock<TestCa> = OnceLock::new();
+        CA.get_or_init(|| TestCa::generate(Product::Genoa).unwrap())
+    }
+
+    /// Serve a Genoa chain and a VCEK for `CHIP` at `TCB` from a mock KDS
+    /// that rate limits the first `inject_429` requests, then answers
+    /// `inject_404` with 404. Returns a client allowed `retries` retries.
+    fn serve(inject_429: u32, inject_404: u32, retries: u32) -> (KdsClient, TempDir) {
+        let dir = TempDir::new("kds");
+        let ca = ca();
+        dir.write(
+            "cert_chain.pem",
+            [ca.ask.to_pem().unwrap(), ca.ark.to_pem().unwrap()].concat(),
+        );
+        let (vcek, _) = ca
+            .issue_vcek(&VcekExtensions {
+                product_name: Product::Genoa.vcek_product_name().to_string(),
+                bl_spl: TCB.bootloader,
+                tee_spl: TCB.tee,
+                snp_spl: TCB.snp,
+                ucode_spl: TCB.microcode,
+                fmc_spl: None,
+                hw_id: Some(CHIP.to_vec()),
+                csp_id: None,
+            })
+            .unwrap();
+        dir.write("vcek.der", vcek.to_der().unwrap());
+
+        let args = MockKdsArgs {
+            dir: dir.path().to_path_buf(),
+            listen: "127.0.0.1:0".parse().unwrap(),
+            product: Product::Genoa,
+            inject_429,
+            retry_after: 0,
+            inject_404,
+        };
+        let (addr, server) = mock_kds::bind(&args, std::future::pending()).unwrap();
+        tokio::spawn(server);
+
+        let kds = KdsClient::new(&KdsArgs {
+            kds_url: format!("http://{addr}"),
+            kds_proxy: None,
+            kds_ca_cert: None,
+            kds_timeout: 10,
+            kds_retries: retries,
+        })
+        .unwrap();
+        (kds, dir)
+    }
+
+    fn response(retry_after: Option<&str>) -> Response {
+        let mut response = hyper::Response::builder().status(StatusCode::TOO_MANY_REQUESTS);
+        if let Some(value) = retry_after {
+            response = response.header(RETRY_AFTER, value);
+        }
+        response.body("").unwrap().into()
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(
+            retry_after(&response(Some("7"))),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(retry_after(&response(Some(" 0 "))), Some(Duration::ZERO));
+        assert_eq!(retry_after(&response(Some("-1"))), None);
+        assert_eq!(retry_after(&response(Some("soon"))), None);
+        assert_eq!(retry_after(&response(None)), None);
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let later = SystemTime::now() + Duration::from_secs(30);
+        let delay = retry_after(&response(Some(&httpdate::fmt_http_date(later)))).unwrap();
+        // HTTP dates have whole seconds.
+        assert!(delay > Duration::from_secs(28) && delay <= Duration::from_secs(30));
+
+        let past = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(30));
+        assert_eq!(retry_after(&response(Some(&past))), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn backoff_limits() {
+        for attempt in 0..40 {
+            let full = BACKOFF_BASE
+                .saturating_mul(1 << attempt.min(16))
+                .min(BACKOFF_MAX);
+            for _ in 0..20 {
+                let delay = backoff(attempt);
+                assert!(delay >= full / 2 && delay <= full, "{attempt}: {delay:?}");
+                assert!(delay <= BACKOFF_MAX);
+            }
+        }
+        assert!(backoff(0) <= BACKOFF_BASE);
+        assert!(backoff(u32::MAX) >= BACKOFF_MAX / 2);
+    }
+
+    #[tokio::test]
+    async fn vcek_found() {
+        let (kds, _dir) = serve(0, 0, 0);
+        let vcek = kds.vcek(Product::Genoa, &CHIP, TCB).await.unwrap();
+        let vcek = certs::parse_cert(&vcek).unwrap();
+        assert_eq!(VcekExtensions::from_cert(&vcek).unwrap().tcb(), TCB);
+    }
+
+    #[tokio::test]
+    async fn not_found_is_unknown_chip() {
+        let (kds, _dir) = serve(0, 1, 0);
+        for chip in [CHIP, [0x43; 64]] {
+            let err = kds.vcek(Product::Genoa, &chip, TCB).await.unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<KdsError>(),
+                    Some(KdsError::UnknownChip { product: Product::Genoa, hw_id })
+                        if *hw_id == hex::encode(chip)
+                ),
+                "{err:#}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_request_is_bad_tcb() {
+        let (kds, _dir) = serve(0, 0, 0);
+        let tcb = Tcb { snp: 23, ..TCB };
+        let err = kds.vcek(Product::Genoa, &CHIP, tcb).await.unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<KdsError>(),
+                Some(KdsError::BadTcb { tcb: rejected, message })
+                    if *rejected == tcb && message.starts_with("No VCEK for this chip")
+            ),
+            "{err:#}"
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limit_then_success() {
+        let (kds, _dir) = serve(2, 0, 2);
+        kds.vcek(Product::Genoa, &CHIP, TCB).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn retries_do_not_cover_client_errors() {
+        // The injected 404 follows the 429 and is returned without retrying.
+        let (kds, _dir) = serve(1, 1, 5);
+        let err = kds.vcek(Product::Genoa, &CHIP, TCB).await.unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<KdsError>(),
+                Some(KdsError::UnknownChip { .. })
+            ),
+            "{err:#}"
+        );
+    }
+}
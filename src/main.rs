@@ -1,17 +1,76 @@
+mod cache;
+mod certs;
+mod config;
+mod crl;
+mod decoded;
+mod der;
+mod extensions;
+mod firmware;
+mod igvm;
+mod kds;
+mod measure;
+mod mock_kds;
+mod output;
+mod ovmf;
+mod pki;
+mod product;
+mod registry;
+mod report;
+mod rules;
+mod sim;
+mod tcb;
+mod tcb_policy;
+#[cfg(test)]
+mod testutil;
+mod verify;
+mod vmsa;
+
 use anyhow::Context;
-use clap::{Parser, Subcommand};
+use cache::{CacheArgs, CertCache, EntryKind};
+use clap::{Parser, Subcommand, ValueEnum};
+use crl::CrlPolicy;
+use decoded::DecodedReport;
+use extensions::VcekExtensions;
+use firmware::{Backend, BackendArgs, ReportProvider};
 use hex::encode;
-use sev::firmware::guest::{AttestationReport, Firmware};
+use kds::{KdsArgs, KdsClient};
+use openssl::{bn::BigNum, x509::X509};
+use output::OutputFormat;
+use pki::TestCa;
+use product::Product;
+use registry::Registry;
+use report::SigningKey;
+use rules::{Claims, RuleStatus, Rules};
+use sev::firmware::{
+    guest::{AttestationReport, DerivedKey, GuestFieldSelect},
+    host::{CertTableEntry, CertType},
+};
 use std::{
-    fs::{self, File},
-    io::Write,
-    path::PathBuf,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::Duration,
 };
+use tcb::Tcb;
+use tcb_policy::TcbPolicy;
+use verify::{MatchedImage, Verification};
+
+/// Where fetch-vcek saves the VCEK and verify looks for it by default.
+const DEFAULT_VCEK: &str = "certs/VCEK.bin";
 
 #[derive(Parser)]
 #[command(name = "sev-tool")]
 #[command(about = "AMD SEV management tool")]
 struct Cli {
+    #[command(flatten)]
+    backend: BackendArgs,
+
+    #[command(flatten)]
+    cache: CacheArgs,
+
+    #[command(flatten)]
+    kds: KdsArgs,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -19,97 +78,1181 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     FetchVcek {
-        #[arg(short, long, default_value = "certs/VCEK.bin")]
+        #[arg(short, long, default_value = DEFAULT_VCEK)]
         output: String,
+
+        /// Also download the ASK/ARK chain next to the VCEK.
+        #[arg(long)]
+        with_ca: bool,
+
+        /// Processor product line; detected from the report or host CPU if omitted.
+        #[arg(long, value_enum)]
+        product: Option<Product>,
     },
-    Report,
+    /// Download the ASK/ARK certificate chain from AMD KDS.
+    FetchCa {
+        #[arg(short, long, default_value = "certs")]
+        output_dir: PathBuf,
+
+        /// Processor product line; detected from the host CPU if omitted.
+        #[arg(long, value_enum)]
+        product: Option<Product>,
+
+        /// Download the ASVK/ARK chain that issues VLEKs instead.
+        #[arg(long)]
+        vlek: bool,
+    },
+    /// Download the product's certificate revocation list from AMD KDS.
+    FetchCrl {
+        #[arg(short, long, default_value = "certs/crl.der")]
+        output: PathBuf,
+
+        /// Processor product line; detected from the host CPU if omitted.
+        #[arg(long, value_enum)]
+        product: Option<Product>,
+
+        /// Download the CRL covering VLEK signing keys instead.
+        #[arg(long)]
+        vlek: bool,
+    },
+    /// Request an attestation report and print it, or decode a saved one.
+    Report(ReportCommand),
+    /// Request a key derived from the VCEK or VMRK and print it in hex.
+    DeriveKey(DeriveKeyArgs),
+    /// Inspect and maintain the local certificate cache.
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Inspect or convert certificate files.
+    #[command(subcommand)]
+    Cert(CertCommand),
+    /// Check the ARK -> ASK -> VCEK (or ASVK -> VLEK) chain and the report signature.
+    Verify(VerifyArgs),
+    /// Generate a fake ARK, ASK and VCEKs matching AMD's certificate profile.
+    GenTestPki(GenTestPkiArgs),
+    /// Serve a directory of test certificates over the KDS HTTP API.
+    MockKds(mock_kds::MockKdsArgs),
+    /// Compute the expected SNP launch digest of a QEMU guest booting OVMF or an IGVM image.
+    Measure(measure::MeasureArgs),
 }
 
-async fn request_vcek(
-    chip_id: [u8; 64],
-    reported_tcb: sev::firmware::host::TcbVersion,
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List cached VCEKs and certificate chains.
+    List,
+    /// Show the cached VCEK for a report, if any.
+    Lookup {
+        /// Raw attestation report; a fresh one is requested from the backend if omitted.
+        #[arg(long)]
+        report: Option<PathBuf>,
+
+        #[arg(long, value_enum)]
+        product: Option<Product>,
+    },
+    /// Remove expired or unreadable entries, and optionally old ones.
+    Prune {
+        /// Also remove entries downloaded more than this many days ago.
+        #[arg(long)]
+        older_than_days: Option<u64>,
+
+        /// Only prune entries for this product.
+        #[arg(long, value_enum)]
+        product: Option<Product>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CertCommand {
+    /// Print the fields and AMD extensions of every certificate in a file.
+    Inspect {
+        /// DER certificate, or PEM certificate or bundle.
+        input: PathBuf,
+    },
+    /// Re-encode certificates, or join several files into one PEM bundle.
+    Convert {
+        /// DER or PEM files; a bundle output keeps their order.
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[arg(long, value_enum)]
+        to: CertFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CertFormat {
+    /// A single DER certificate, as written by fetch-vcek.
+    Der,
+    /// A single PEM certificate.
+    Pem,
+    /// Any number of concatenated PEM certificates, as served by KDS.
+    Bundle,
+}
+
+#[derive(clap::Args)]
+#[command(args_conflicts_with_subcommands = true)]
+struct ReportCommand {
+    #[command(subcommand)]
+    command: Option<ReportSubcommand>,
+
+    #[command(flatten)]
+    args: ReportArgs,
+}
+
+#[derive(Subcommand)]
+enum ReportSubcommand {
+    /// Decode a raw report file, e.g. one sent by a guest, without SEV hardware.
+    Decode {
+        /// Raw 1184-byte report, or - for stdin.
+        #[arg(short, long)]
+        input: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Processor product line, for decoding TCBs; required for version 2 reports.
+        #[arg(long, value_enum)]
+        product: Option<Product>,
+    },
+}
+
+#[derive(clap::Args)]
+struct ReportArgs {
+    /// Hex report data, e.g. a verifier nonce. Up to 64 bytes are zero-padded,
+    /// longer data is replaced by its SHA-512 digest, as for --data-file.
+    #[arg(long, group = "data")]
+    data_hex: Option<String>,
+
+    /// Read the report data from a file.
+    #[arg(long, group = "data")]
+    data_file: Option<PathBuf>,
+
+    /// Read the report data from standard input.
+    #[arg(long, group = "data")]
+    data_stdin: bool,
+
+    /// VMPL to request the report at; defaults to the guest's current VMPL.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=3))]
+    vmpl: Option<u32>,
+
+    /// Also save the raw 1184-byte report to this file.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Processor product line, for decoding TCBs; detected from the report or host CPU if omitted.
+    #[arg(long, value_enum)]
+    product: Option<Product>,
+
+    /// Request an extended report and save the host's certificate table.
+    #[arg(long)]
+    extended: bool,
+
+    /// Where --extended writes the certificate table entries.
+    #[arg(long, default_value = "certs")]
+    certs_dir: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct DeriveKeyArgs {
+    /// Derive from the VMRK instead of the VCEK.
+    #[arg(long)]
+    vmrk: bool,
+
+    /// Guest fields to mix into the key, e.g. measurement,guest-svn.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    mix: Vec<KeyField>,
+
+    /// VMPL to mix into the key; at least the guest's current VMPL.
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=3))]
+    vmpl: u32,
+
+    /// Guest SVN to mix into the key; at most the SVN of the ID block.
+    #[arg(long, default_value_t = 0)]
+    guest_svn: u32,
+
+    /// Raw TCB version to mix into the key; at most the committed TCB.
+    #[arg(long, value_parser = measure::parse_u64, default_value = "0")]
+    tcb_version: u64,
+}
+
+/// A guest field MSG_KEY_REQ can mix into a derived key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum KeyField {
+    Policy,
+    ImageId,
+    FamilyId,
+    Measurement,
+    GuestSvn,
+    TcbVersion,
+}
+
+#[derive(clap::Args)]
+struct VerifyArgs {
+    /// Raw attestation report; a fresh one is requested from the backend if omitted.
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// VCEK certificate in DER or PEM encoding; defaults to certs/VCEK.bin if
+    /// present, then the extended report certificate table, then the cache or KDS.
+    #[arg(long)]
+    vcek: Option<PathBuf>,
+
+    /// ARK certificate; defaults to ark.pem next to the VCEK or VLEK, the
+    /// certificate table, the built-in AMD ARK, then KDS.
+    #[arg(long)]
+    ark: Option<PathBuf>,
+
+    /// ASK certificate; defaults to ask.pem next to the VCEK, the certificate
+    /// table, the built-in AMD ASK, then KDS.
+    #[arg(long)]
+    ask: Option<PathBuf>,
+
+    /// VLEK certificate, for VLEK-signed reports; defaults to the extended report certificate table.
+    #[arg(long)]
+    vlek: Option<PathBuf>,
+
+    /// ASVK certificate; defaults to asvk.pem next to the VLEK, the certificate table, then KDS.
+    #[arg(long)]
+    asvk: Option<PathBuf>,
+
+    /// Processor product line; detected from the report or host CPU if omitted.
+    #[arg(long, value_enum)]
+    product: Option<Product>,
+
+    /// Trust the ARK with this SHA-256 key fingerprint instead of AMD's, e.g. a gen-test-pki ARK.
+    /// Required for Turin, whose ARK is not pinned.
+    #[arg(long)]
+    ark_fingerprint: Option<String>,
+
+    /// CRL in DER or PEM encoding; taken from the certificate table, else
+    /// crl.der next to the VCEK or VLEK, else the cache or KDS, if omitted.
+    #[arg(long)]
+    crl: Option<PathBuf>,
+
+    /// What to do when the CRL is unavailable, invalid or stale.
+    #[arg(long, value_enum, default_value_t = CrlPolicy::Warn)]
+    crl_policy: CrlPolicy,
+
+    /// Registry of approved measurements: a TOML or JSON file, or a directory of them.
+    #[arg(long)]
+    measurements: Option<PathBuf>,
+
+    /// TOML or JSON file of minimum TCB component versions per product.
+    #[arg(long)]
+    tcb_policy: Option<PathBuf>,
+
+    /// TOML or JSON file of named acceptance rules over the report's claims.
+    #[arg(long)]
+    rules: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(clap::Args)]
+struct GenTestPkiArgs {
+    #[arg(short, long, default_value = "test-pki")]
+    output_dir: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = Product::Genoa)]
+    product: Product,
+
+    /// Hex chip ID to issue a VCEK for; repeatable. Defaults to one random chip.
+    #[arg(long = "chip-id")]
+    chip_ids: Vec<String>,
+
+    #[arg(long, default_value_t = 0)]
+    bl_spl: u8,
+
+    #[arg(long, default_value_t = 0)]
+    tee_spl: u8,
+
+    #[arg(long, default_value_t = 0)]
+    snp_spl: u8,
+
+    #[arg(long, default_value_t = 0)]
+    ucode_spl: u8,
+
+    /// FMC SPL, only carried by Turin VCEKs.
+    #[arg(long)]
+    fmc_spl: Option<u8>,
+
+    /// Hex certificate serial to list in the CRL, e.g. 10001 for the ASK; repeatable.
+    #[arg(long = "revoke")]
+    revoked: Vec<String>,
+
+    /// Days until the CRL's nextUpdate; negative values produce a stale CRL.
+    #[arg(long, default_value_t = 30, allow_negative_numbers = true)]
+    crl_next_update_days: i64,
+
+    /// Also generate an ASVK and a VLEK with the same SPLs.
+    #[arg(long)]
+    vlek: bool,
+
+    /// Cloud service provider ID carried by the VLEK.
+    #[arg(long, default_value = "test-csp")]
+    csp_id: String,
+}
+
+/// The KDS `cert_chain` PEM bundle for `key`, from the cache or KDS.
+async fn fetch_cert_chain(
+    product: Product,
+    key: SigningKey,
+    cache_args: &CacheArgs,
+    kds_args: &KdsArgs,
 ) -> anyhow::Result<Vec<u8>> {
-    const KDS_CERT_SITE: &str = "https://kdsintf.amd.com";
-    const KDS_VCEK: &str = "/vcek/v1";
-    let hw_id: String = encode(&chip_id);
-
-    let vcek_url = format!(
-        "{KDS_CERT_SITE}{KDS_VCEK}/Genoa/\
-        {hw_id}?blSPL={:02}&teeSPL={:02}&snpSPL={:02}&ucodeSPL={:02}",
-        reported_tcb.bootloader, reported_tcb.tee, reported_tcb.snp, reported_tcb.microcode
-    );
+    let cache = CertCache::open(cache_args)?;
+    if let Some(chain) = cache
+        .as_ref()
+        .and_then(|cache| cache.get_cert_chain(product, key))
+    {
+        return Ok(chain);
+    }
 
-    loop {
-        let response = reqwest::get(&vcek_url)
-            .await
-            .context("Failed to get VCEK from URL");
-
-        match response {
-            Ok(response) => {
-                if response.status() == 429 {
-                    println!("Received 429, sleeping for 10 seconds");
-                    std::thread::sleep(std::time::Duration::from_secs(10));
-                    continue;
-                }
-                let rsp_bytes = response.bytes().await?.to_vec();
-                return Ok(rsp_bytes);
-            }
-            Err(e) => return Err(e.into()),
+    cache::require_online(
+        cache_args,
+        &format!("The {product} {} certificate chain", key.issuer_name()),
+    )?;
+    let chain = KdsClient::new(kds_args)?
+        .cert_chain(product, key)
+        .await
+        .context("Failed to fetch certificate chain")?;
+    if let Some(cache) = &cache {
+        cache.put_cert_chain(product, key, &chain)?;
+    }
+    Ok(chain)
+}
+
+/// Download the ASK (or ASVK) and ARK for `product` into `dir` as ask.pem
+/// (or asvk.pem) and ark.pem.
+async fn fetch_ca(
+    product: Product,
+    key: SigningKey,
+    dir: &Path,
+    cache_args: &CacheArgs,
+    kds_args: &KdsArgs,
+) -> anyhow::Result<()> {
+    let chain = fetch_cert_chain(product, key, cache_args, kds_args).await?;
+    let (ask, ark) = certs::split_cert_chain(&chain)
+        .with_context(|| format!("Invalid cached {product} certificate chain"))?;
+
+    let issuer = key.issuer_name();
+    let ask_file = format!("{}.pem", issuer.to_lowercase());
+    certs::write_atomic(&dir.join(ask_file), &ask.to_pem()?)?;
+    certs::write_atomic(&dir.join("ark.pem"), &ark.to_pem()?)?;
+
+    println!("{issuer} and ARK certificates saved to {}", dir.display());
+    Ok(())
+}
+
+/// The CRL for `product`, from the cache while it is fresh, else from KDS.
+/// Offline, a stale cached CRL is returned for the caller's policy to judge.
+async fn fetch_crl(
+    product: Product,
+    key: SigningKey,
+    cache_args: &CacheArgs,
+    kds_args: &KdsArgs,
+) -> anyhow::Result<Vec<u8>> {
+    let cache = CertCache::open(cache_args)?;
+    let cached = cache.as_ref().and_then(|cache| cache.get_crl(product, key));
+    if let Some(cached) = cached {
+        let fresh = crl::parse(&cached).is_ok_and(|crl| crl::is_stale(&crl).is_ok_and(|s| !s));
+        if fresh || cache_args.offline {
+            return Ok(cached);
         }
+        log::info!("Cached {product} CRL is stale, downloading a new one");
     }
+
+    cache::require_online(cache_args, &format!("The {product} CRL"))?;
+    let crl = KdsClient::new(kds_args)?
+        .crl(product, key)
+        .await
+        .context("Failed to fetch CRL")?;
+    if let Some(cache) = &cache {
+        cache.put_crl(product, key, &crl)?;
+    }
+    Ok(crl)
 }
 
-async fn fetch_vcek(output_path: &str) -> anyhow::Result<()> {
+async fn fetch_vcek(
+    fw: &mut dyn ReportProvider,
+    product: Option<Product>,
+    output_path: &str,
+    cache_args: &CacheArgs,
+    kds_args: &KdsArgs,
+) -> anyhow::Result<Product> {
     let unique_data = [0u8; 64];
 
-    let mut fw = Firmware::open().context("Failed to open firmware")?;
-
     let report = fw
         .get_report(None, Some(unique_data), None)
         .context("Failed to get attestation report")?;
 
-    let vcek = request_vcek(report.chip_id, report.reported_tcb)
-        .await
-        .context("Failed to fetch VCEK")?;
+    let product = product::detect(product, Some(&report))?;
+    let vcek = vcek_for_report(&report, product, cache_args, kds_args).await?;
+    certs::write_atomic(Path::new(output_path), &vcek)?;
+
+    println!("VCEK certificate saved to {}", output_path);
+    Ok(product)
+}
 
-    if let Some(parent) = PathBuf::from(output_path).parent() {
-        fs::create_dir_all(parent)?;
+/// The VCEK that signed `report`, from the cache or KDS.
+async fn vcek_for_report(
+    report: &AttestationReport,
+    product: Product,
+    cache_args: &CacheArgs,
+    kds_args: &KdsArgs,
+) -> anyhow::Result<Vec<u8>> {
+    match report::signing_key(report)? {
+        SigningKey::Vcek => {}
+        SigningKey::Vlek => anyhow::bail!(
+            "Report is signed by a VLEK, which KDS does not serve per chip; pass it to \
+             `verify --vlek` or let verify read it from the extended report certificate table"
+        ),
+        SigningKey::None => anyhow::bail!("Report is unsigned, there is no VCEK to fetch"),
+    }
+    if report::chip_id_masked(report) {
+        anyhow::bail!(
+            "Report chip ID is all zeros (MASK_CHIP_ID is set), KDS cannot look up its VCEK"
+        );
     }
+    let tcb = Tcb::from_version(product, &report.reported_tcb)?;
 
-    let mut file = File::create(output_path)?;
-    file.write_all(&vcek)?;
+    let hw_id = &report.chip_id[..product.hw_id_len()];
 
-    println!("VCEK certificate saved to {}", output_path);
+    let cache = CertCache::open(cache_args)?;
+    let vcek = match cache
+        .as_ref()
+        .and_then(|cache| cache.get_vcek(product, hw_id, tcb))
+    {
+        Some(vcek) => vcek,
+        None => {
+            cache::require_online(cache_args, "The VCEK for this chip and TCB")?;
+            let vcek = KdsClient::new(kds_args)?
+                .vcek(product, hw_id, tcb)
+                .await
+                .context("Failed to fetch VCEK")?;
+
+            let cert = certs::parse_cert(&vcek)?;
+            verify::key_matches_report(&cert, report, product, SigningKey::Vcek)
+                .context("Fetched VCEK does not match the report")?;
+
+            if let Some(cache) = &cache {
+                cache.put_vcek(product, hw_id, tcb, &vcek)?;
+            }
+            vcek
+        }
+    };
+
+    certs::parse_cert(&vcek).context("Cached VCEK is not a valid certificate")?;
+    Ok(vcek)
+}
+
+/// The REPORT_DATA selected by the --data-* options, all zeros by default.
+fn report_data(args: &ReportArgs) -> anyhow::Result<[u8; 64]> {
+    let input = if let Some(data) = &args.data_hex {
+        hex::decode(data.trim()).context("--data-hex is not valid hex")?
+    } else if let Some(path) = &args.data_file {
+        fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?
+    } else if args.data_stdin {
+        let mut input = Vec::new();
+        io::stdin()
+            .read_to_end(&mut input)
+            .context("Failed to read report data from stdin")?;
+        input
+    } else {
+        Vec::new()
+    };
+    Ok(report::report_data(&input))
+}
+
+fn display_report(fw: &mut dyn ReportProvider, args: &ReportArgs) -> anyhow::Result<()> {
+    let unique_data = report_data(args)?;
+
+    let (report, table) = if args.extended {
+        fw.get_ext_report(None, Some(unique_data), args.vmpl)
+            .context("Failed to get extended attestation report")?
+    } else {
+        let report: AttestationReport = fw
+            .get_report(None, Some(unique_data), args.vmpl)
+            .context("Failed to get attestation report")?;
+        (report, Vec::new())
+    };
+
+    let product = product::detect(args.product, Some(&report))?;
+    output::emit(args.format, &DecodedReport::new(&report, product)?)?;
+
+    if let Some(path) = &args.output {
+        certs::write_atomic(path, &report::to_bytes(&report)?)?;
+        output::status(args.format, format!("Report saved to {}", path.display()));
+    }
+    if args.extended {
+        let key = report::signing_key(&report)?;
+        save_cert_table(&table, key, &args.certs_dir, args.format)?;
+    }
     Ok(())
 }
 
-fn display_report() -> anyhow::Result<()> {
-    let unique_data = [0u8; 64];
-    env_logger::builder().format_timestamp(None).init();
+fn derive_key(fw: &mut dyn ReportProvider, args: &DeriveKeyArgs) -> anyhow::Result<()> {
+    let mut select = GuestFieldSelect::default();
+    for field in &args.mix {
+        match field {
+            KeyField::Policy => select.set_guest_policy(1),
+            KeyField::ImageId => select.set_image_id(1),
+            KeyField::FamilyId => select.set_family_id(1),
+            KeyField::Measurement => select.set_measurement(1),
+            KeyField::GuestSvn => select.set_svn(1),
+            KeyField::TcbVersion => select.set_tcb_version(1),
+        }
+    }
+    let request = DerivedKey::new(
+        args.vmrk,
+        select,
+        args.vmpl,
+        args.guest_svn,
+        args.tcb_version,
+    );
+    let key = fw
+        .get_derived_key(None, request)
+        .context("Failed to get derived key")?;
+    println!("{}", encode(key));
+    Ok(())
+}
 
-    let mut fw = Firmware::open().context("Failed to open firmware")?;
+fn decode_report(
+    input: &Path,
+    format: OutputFormat,
+    product: Option<Product>,
+) -> anyhow::Result<()> {
+    let (bytes, name) = if input == Path::new("-") {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .context("Failed to read report from stdin")?;
+        (bytes, "stdin".to_string())
+    } else {
+        let bytes = fs::read(input)
+            .with_context(|| format!("Failed to read report {}", input.display()))?;
+        (bytes, input.display().to_string())
+    };
+    let report = report::from_bytes(&bytes).with_context(|| format!("Invalid report {name}"))?;
 
-    log::info!("Opened firmware interface");
+    // The host this runs on is not the one that produced the report.
+    let product = product::for_report(&report, product)?;
+    output::emit(format, &DecodedReport::new(&report, product)?)
+}
 
-    let report: AttestationReport = fw
-        .get_report(None, Some(unique_data), None)
-        .context("Failed to get attestation report")?;
+/// Write every entry of a host-supplied certificate table into `dir`.
+fn save_cert_table(
+    table: &[CertTableEntry],
+    key: SigningKey,
+    dir: &Path,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    if table.is_empty() {
+        output::status(format, "The host supplied no certificates");
+        return Ok(());
+    }
 
-    println!("{:#?}", report);
+    output::status(format, "Certificate table:");
+    for entry in table {
+        let name = certs::table_entry_name(&entry.cert_type, key);
+        let (file, contents) = certs::table_entry_file(entry, key)
+            .with_context(|| format!("Invalid {name} entry in the certificate table"))?;
+        let path = dir.join(file);
+        certs::write_atomic(&path, &contents)?;
+        output::status(format, format!("  {name:<6} {}", path.display()));
+    }
+    Ok(())
+}
+
+fn load_report(backend: &BackendArgs, path: Option<&Path>) -> anyhow::Result<AttestationReport> {
+    match path {
+        Some(path) => {
+            let bytes = fs::read(path)
+                .with_context(|| format!("Failed to read report {}", path.display()))?;
+            report::from_bytes(&bytes)
+        }
+        None => firmware::open(backend)?
+            .get_report(None, Some([0u8; 64]), None)
+            .context("Failed to get attestation report"),
+    }
+}
+
+fn cache_command(
+    backend: &BackendArgs,
+    cache_args: &CacheArgs,
+    command: &CacheCommand,
+) -> anyhow::Result<()> {
+    let cache = CertCache::open(cache_args)?.context("The cache is disabled by --no-cache")?;
 
+    match command {
+        CacheCommand::List => {
+            for entry in cache.entries()? {
+                match entry.kind {
+                    EntryKind::CertChain(key) => {
+                        println!("{:<6} {}cert_chain", entry.product, cache_prefix(key))
+                    }
+                    EntryKind::Crl(key) => {
+                        println!("{:<6} {}crl", entry.product, cache_prefix(key))
+                    }
+                    EntryKind::Vcek { hw_id, tcb } => {
+                        println!("{:<6} vcek  {hw_id}  {tcb}", entry.product)
+                    }
+                }
+            }
+        }
+        CacheCommand::Lookup { report, product } => {
+            let report = load_report(backend, report.as_deref())?;
+            let product = product::detect(*product, Some(&report))?;
+            let tcb = Tcb::from_version(product, &report.reported_tcb)?;
+            let hw_id = &report.chip_id[..product.hw_id_len()];
+            let path = cache.vcek_path(product, hw_id, tcb);
+            if !path.is_file() {
+                anyhow::bail!(
+                    "No cached {product} VCEK for chip {} at {tcb}",
+                    encode(hw_id)
+                );
+            }
+            println!("{}", path.display());
+        }
+        CacheCommand::Prune {
+            older_than_days,
+            product,
+        } => {
+            let max_age = older_than_days.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+            let removed = cache.prune(*product, max_age)?;
+            for entry in &removed {
+                println!("Removed {}", entry.path.display());
+            }
+            println!(
+                "Pruned {} entries from {}",
+                removed.len(),
+                cache.root().display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn inspect_certs(path: &Path) -> anyhow::Result<()> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read certificate {}", path.display()))?;
+    let certs = certs::parse_certs(&bytes)
+        .with_context(|| format!("Failed to load certificates from {}", path.display()))?;
+
+    for (i, cert) in certs.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let validity = if certs::is_current(cert)? {
+            "currently valid"
+        } else {
+            "NOT currently valid"
+        };
+        let serial = cert.serial_number().to_bn()?.to_hex_str()?.to_string();
+
+        println!("Certificate {}:", i + 1);
+        println!(
+            "  Subject:         {}",
+            certs::name_to_string(cert.subject_name())
+        );
+        println!(
+            "  Issuer:          {}",
+            certs::name_to_string(cert.issuer_name())
+        );
+        println!("  Serial:          0x{serial}");
+        println!("  Not before:      {}", cert.not_before());
+        println!("  Not after:       {} ({validity})", cert.not_after());
+        println!("  Key:             {}", certs::key_algorithm(cert)?);
+        println!("  Signature:       {}", cert.signature_algorithm().object());
+        println!(
+            "  SHA-256:         {}",
+            encode(cert.digest(openssl::hash::MessageDigest::sha256())?)
+        );
+        println!("  Key SHA-256:     {}", certs::key_fingerprint(cert)?);
+
+        let amd = extensions::describe(cert)?;
+        if !amd.is_empty() {
+            println!("  AMD extensions:");
+            for (name, value) in amd {
+                println!("    {name:<14} {value}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn convert_certs(inputs: &[PathBuf], output: &Path, to: CertFormat) -> anyhow::Result<()> {
+    let mut certs = Vec::new();
+    for path in inputs {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Failed to read certificate {}", path.display()))?;
+        certs.extend(
+            certs::parse_certs(&bytes)
+                .with_context(|| format!("Failed to load certificates from {}", path.display()))?,
+        );
+    }
+
+    let contents = match (to, certs.as_slice()) {
+        (CertFormat::Der, [cert]) => cert.to_der()?,
+        (CertFormat::Pem, [cert]) => cert.to_pem()?,
+        (CertFormat::Der | CertFormat::Pem, certs) => anyhow::bail!(
+            "DER and PEM output hold exactly one certificate, found {}; use --to bundle",
+            certs.len()
+        ),
+        (CertFormat::Bundle, certs) => certs
+            .iter()
+            .map(|cert| cert.to_pem())
+            .collect::<Result<Vec<_>, _>>()?
+            .concat(),
+    };
+    certs::write_atomic(output, &contents)?;
+
+    println!(
+        "Wrote {} certificate(s) to {}",
+        certs.len(),
+        output.display()
+    );
     Ok(())
 }
 
+fn cache_prefix(key: SigningKey) -> &'static str {
+    match key {
+        SigningKey::Vlek => "vlek/",
+        _ => "",
+    }
+}
+
+/// The ARK, ASK (or ASVK) and VCEK (or VLEK) for `report`. Each comes from
+/// its explicit path, a file next to the VCEK or VLEK, the extended report
+/// certificate table, the built-in AMD certificates, and finally the cache or
+/// KDS, in that order.
+/// The VCEK or VLEK file to verify with, if one is given or at its default path.
+fn leaf_path(args: &VerifyArgs, key: SigningKey) -> Option<PathBuf> {
+    match key {
+        SigningKey::Vcek => args
+            .vcek
+            .clone()
+            .or_else(|| Some(PathBuf::from(DEFAULT_VCEK)).filter(|path| path.exists())),
+        _ => args.vlek.clone(),
+    }
+}
+
+async fn load_chain(
+    cache_args: &CacheArgs,
+    kds_args: &KdsArgs,
+    args: &VerifyArgs,
+    report: &AttestationReport,
+    table: &[CertTableEntry],
+    product: Product,
+    key: SigningKey,
+) -> anyhow::Result<(X509, X509, X509)> {
+    let leaf_path = leaf_path(args, key);
+    let dir = leaf_path.as_deref().and_then(Path::parent);
+    let load = |explicit: Option<&PathBuf>, name: &str, cert_type: CertType| {
+        let path = match (explicit, dir) {
+            (Some(path), _) => path.clone(),
+            (None, Some(dir)) if dir.join(name).exists() => dir.join(name),
+            _ => return certs::from_cert_table(table, &cert_type),
+        };
+        certs::load_cert(&path).map(Some)
+    };
+
+    let (ask_path, ask_file) = match key {
+        SigningKey::Vlek => (args.asvk.as_ref(), "asvk.pem"),
+        _ => (args.ask.as_ref(), "ask.pem"),
+    };
+    let mut ark = load(args.ark.as_ref(), "ark.pem", CertType::ARK)?;
+    let mut ask = load(ask_path, ask_file, CertType::ASK)?;
+    let leaf = match &leaf_path {
+        Some(path) => Some(certs::load_cert(path)?),
+        None => certs::from_cert_table(table, &certs::cert_type(key))?,
+    };
+    let leaf = match (leaf, key) {
+        (Some(leaf), _) => leaf,
+        (None, SigningKey::Vcek) => {
+            log::info!("No VCEK given or in the certificate table, fetching it");
+            let vcek = vcek_for_report(report, product, cache_args, kds_args).await?;
+            certs::parse_cert(&vcek)?
+        }
+        (None, _) => anyhow::bail!("No VLEK in the certificate table, pass --vlek"),
+    };
+
+    if key == SigningKey::Vcek && (ark.is_none() || ask.is_none()) {
+        if let Some((builtin_ark, builtin_ask)) = certs::builtin_ca(product)? {
+            log::info!("Using the built-in {product} ARK and ASK");
+            ark = ark.or(Some(builtin_ark));
+            ask = ask.or(Some(builtin_ask));
+        }
+    }
+    let (ark, ask) = match (ark, ask) {
+        (Some(ark), Some(ask)) => (ark, ask),
+        (ark, ask) => {
+            let chain = fetch_cert_chain(product, key, cache_args, kds_args).await?;
+            let (kds_ask, kds_ark) = certs::split_cert_chain(&chain).with_context(|| {
+                format!("Invalid {product} {} certificate chain", key.issuer_name())
+            })?;
+            (ark.unwrap_or(kds_ark), ask.unwrap_or(kds_ask))
+        }
+    };
+    Ok((ark, ask, leaf))
+}
+
+async fn verify_report(
+    backend: &BackendArgs,
+    cache_args: &CacheArgs,
+    kds_args: &KdsArgs,
+    args: &VerifyArgs,
+) -> anyhow::Result<()> {
+    let mut verification = Verification::default();
+    let result = run_verification(backend, cache_args, kds_args, args, &mut verification).await;
+    verification.finish(args.format, result)
+}
+
+async fn run_verification(
+    backend: &BackendArgs,
+    cache_args: &CacheArgs,
+    kds_args: &KdsArgs,
+    args: &VerifyArgs,
+    verification: &mut Verification,
+) -> anyhow::Result<()> {
+    let tcb_policy = args
+        .tcb_policy
+        .as_deref()
+        .map(TcbPolicy::load)
+        .transpose()?;
+    let registry = args
+        .measurements
+        .as_deref()
+        .map(Registry::load)
+        .transpose()?;
+    let rules = args.rules.as_deref().map(Rules::load).transpose()?;
+
+    // A fresh report comes with whatever certificates the host supplies.
+    let (report, table) = match &args.report {
+        Some(_) => (load_report(backend, args.report.as_deref())?, Vec::new()),
+        None => firmware::open(backend)?
+            .get_ext_report(None, Some([0u8; 64]), None)
+            .context("Failed to get extended attestation report")?,
+    };
+    // Only a fresh hardware report is known to come from this host.
+    let from_host = args.report.is_none() && matches!(backend.backend, Backend::Hardware);
+    let product = if from_host {
+        product::detect(args.product, Some(&report))?
+    } else {
+        product::for_report(&report, args.product)?
+    };
+    let decoded = DecodedReport::new(&report, product)?;
+    for warning in &decoded.warnings {
+        log::warn!("{warning}");
+    }
+    verification.report = Some(decoded);
+    let key = report::signing_key(&report)?;
+    verification.signing_key = Some(key);
+    if key == SigningKey::None {
+        anyhow::bail!("Report is unsigned, there is nothing to verify");
+    }
+    log::info!("Report is signed by a {key}");
+    if !table.is_empty() {
+        log::info!("Host supplied {} certificate table entries", table.len());
+    }
+    let ark_fingerprint = match args.ark_fingerprint.as_deref() {
+        Some(fingerprint) => fingerprint,
+        None => verify::amd_ark_fingerprint(product).with_context(|| {
+            format!("No AMD ARK is pinned for {product}, pass --ark-fingerprint")
+        })?,
+    };
+    let (ark, ask, vcek) =
+        load_chain(cache_args, kds_args, args, &report, &table, product, key).await?;
+
+    verification.check(
+        "cert_chain",
+        "Certificate chain verification",
+        verify::cert_chain(&ark, &ask, &vcek, key, ark_fingerprint),
+    )?;
+
+    if args.crl_policy == CrlPolicy::Skip {
+        verification.skipped("revocation", "Revocation check");
+    } else {
+        let crl = match &args.crl {
+            Some(path) => {
+                fs::read(path).with_context(|| format!("Failed to read CRL {}", path.display()))
+            }
+            None => {
+                // A CRL saved by `report --extended` sits next to the VCEK or VLEK.
+                let saved = leaf_path(args, key)
+                    .and_then(|path| Some(path.parent()?.join("crl.der")))
+                    .filter(|path| path.exists());
+                match (certs::table_entry(&table, &certs::crl_type()), saved) {
+                    (Some(crl), _) => Ok(crl.to_vec()),
+                    (None, Some(path)) => fs::read(&path)
+                        .with_context(|| format!("Failed to read CRL {}", path.display())),
+                    (None, None) => fetch_crl(product, key, cache_args, kds_args).await,
+                }
+            }
+        }
+        .and_then(|bytes| crl::parse(&bytes));
+        let complete = match crl {
+            Ok(crl) => crl::check(&crl, &ark, &ask, &vcek, key, args.crl_policy),
+            Err(e) => args.crl_policy.handle(e.context("No usable CRL")),
+        };
+        match complete {
+            Ok(false) => verification.incomplete(
+                "revocation",
+                "Revocation check",
+                "continuing under --crl-policy warn",
+            ),
+            complete => {
+                verification.check("revocation", "Revocation check", complete.map(|_| ()))?
+            }
+        }
+    }
+
+    verification.check(
+        "key_matches_report",
+        &format!("{key} extension check"),
+        verify::key_matches_report(&vcek, &report, product, key),
+    )?;
+
+    verification.check(
+        "report_signature",
+        "Report signature verification",
+        verify::report_signature(&report, &vcek),
+    )?;
+
+    if let Some(tcb_policy) = &tcb_policy {
+        verification.check_each(
+            "tcb_policy",
+            "TCB policy check",
+            tcb_policy.violations(&report, product)?,
+        )?;
+    }
+
+    if let Some(registry) = &registry {
+        let result = registry.find(&report).map(|image| {
+            verification.matched_image = Some(MatchedImage {
+                name: image.name.clone(),
+                description: image.description.clone(),
+            });
+            Some(format!("matched {}", image.name))
+        });
+        verification.check_noted("measurement", "Measurement registry check", result)?;
+    }
+
+    if let (Some(rules), Some(decoded)) = (&rules, &verification.report) {
+        let image = verification
+            .matched_image
+            .as_ref()
+            .map(|image| image.name.as_str());
+        let results = rules.evaluate(&Claims::new(decoded, image)?);
+        let failures = results
+            .iter()
+            .filter(|result| result.status != RuleStatus::Passed)
+            .map(|result| match &result.message {
+                Some(message) => format!("{}: {message}", result.name),
+                None => result.name.clone(),
+            })
+            .collect();
+        verification.rules = results;
+        verification.check_each("rules", "Policy rules check", failures)?;
+    }
+    Ok(())
+}
+
+fn gen_test_pki(args: &GenTestPkiArgs) -> anyhow::Result<()> {
+    let product = args.product;
+    let mut chip_ids = Vec::new();
+    for chip_id in &args.chip_ids {
+        let chip_id = hex::decode(chip_id).context("Chip ID is not valid hex")?;
+        if chip_id.len() != 64 {
+            anyhow::bail!("Chip ID must be 64 bytes, got {}", chip_id.len());
+        }
+        chip_ids.push(chip_id);
+    }
+    if chip_ids.is_empty() {
+        let mut chip_id = vec![0u8; 64];
+        openssl::rand::rand_bytes(&mut chip_id[..product.hw_id_len()])?;
+        chip_ids.push(chip_id);
+    }
+
+    fs::create_dir_all(&args.output_dir)?;
+    let write = |name: &str, contents: &[u8]| -> anyhow::Result<()> {
+        let path = args.output_dir.join(name);
+        fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))
+    };
+
+    let ca = TestCa::generate(product)?;
+    write("ark.pem", &ca.ark.to_pem()?)?;
+    write("ark-key.pem", &ca.ark_key.private_key_to_pem_pkcs8()?)?;
+    write("ask.pem", &ca.ask.to_pem()?)?;
+    write("ask-key.pem", &ca.ask_key.private_key_to_pem_pkcs8()?)?;
+    write(
+        "cert_chain.pem",
+        &[ca.ask.to_pem()?, ca.ark.to_pem()?].concat(),
+    )?;
+    let revoked = args
+        .revoked
+        .iter()
+        .map(|serial| BigNum::from_hex_str(serial).context("Serial is not valid hex"))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let crl = ca.crl(&revoked, args.crl_next_update_days)?.to_der()?;
+    write("crl.der", &crl)?;
+    println!(
+        "Test {} ARK key fingerprint (SHA-256): {}",
+        ca.product,
+        certs::key_fingerprint(&ca.ark)?
+    );
+
+    for chip_id in chip_ids {
+        let hw_id = chip_id[..product.hw_id_len()].to_vec();
+        let extensions = VcekExtensions {
+            product_name: product.vcek_product_name().to_string(),
+            bl_spl: args.bl_spl,
+            tee_spl: args.tee_spl,
+            snp_spl: args.snp_spl,
+            ucode_spl: args.ucode_spl,
+            fmc_spl: args.fmc_spl,
+            hw_id: Some(hw_id),
+            csp_id: None,
+        };
+        let (vcek, key) = ca.issue_vcek(&extensions)?;
+
+        let stem = format!("vcek-{}", encode(&chip_id[..8]));
+        write(&format!("{stem}.der"), &vcek.to_der()?)?;
+        write(&format!("{stem}-key.pem"), &key.private_key_to_pem()?)?;
+        println!("Issued VCEK for chip {}: {stem}.der", encode(&chip_id));
+    }
+
+    if args.vlek {
+        let (asvk, asvk_key) = ca.generate_asvk()?;
+        write("asvk.pem", &asvk.to_pem()?)?;
+        write("asvk-key.pem", &asvk_key.private_key_to_pem_pkcs8()?)?;
+        write(
+            "vlek_cert_chain.pem",
+            &[asvk.to_pem()?, ca.ark.to_pem()?].concat(),
+        )?;
+        write("vlek_crl.der", &crl)?;
+
+        let extensions = VcekExtensions {
+            product_name: product.vcek_product_name().to_string(),
+            bl_spl: args.bl_spl,
+            tee_spl: args.tee_spl,
+            snp_spl: args.snp_spl,
+            ucode_spl: args.ucode_spl,
+            fmc_spl: args.fmc_spl,
+            hw_id: None,
+            csp_id: Some(args.csp_id.clone()),
+        };
+        let (vlek, key) = ca.issue_vlek(&asvk, &asvk_key, &extensions)?;
+        write("vlek.der", &vlek.to_der()?)?;
+        write("vlek-key.pem", &key.private_key_to_pem()?)?;
+        println!("Issued VLEK for CSP {}: vlek.der", args.csp_id);
+    }
+
+    println!("Test PKI written to {}", args.output_dir.display());
+    Ok(())
+}
+
+fn signing_key(vlek: bool) -> SigningKey {
+    if vlek {
+        SigningKey::Vlek
+    } else {
+        SigningKey::Vcek
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .format_timestamp(None)
+        .init();
 
     match cli.command {
-        Commands::FetchVcek { output } => {
-            fetch_vcek(&output).await?;
+        Commands::FetchVcek {
+            output,
+            with_ca,
+            product,
+        } => {
+            let mut fw = firmware::open(&cli.backend)?;
+            let product = fetch_vcek(fw.as_mut(), product, &output, &cli.cache, &cli.kds).await?;
+            if with_ca {
+                let dir = Path::new(&output).parent().unwrap_or(Path::new("."));
+                fetch_ca(product, SigningKey::Vcek, dir, &cli.cache, &cli.kds).await?;
+            }
+        }
+        Commands::FetchCa {
+            output_dir,
+            product,
+            vlek,
+        } => {
+            fetch_ca(
+                product::detect(product, None)?,
+                signing_key(vlek),
+                &output_dir,
+                &cli.cache,
+                &cli.kds,
+            )
+            .await?;
+        }
+        Commands::FetchCrl {
+            output,
+            product,
+            vlek,
+        } => {
+            let product = product::detect(product, None)?;
+            let crl = fetch_crl(product, signing_key(vlek), &cli.cache, &cli.kds).await?;
+            certs::write_atomic(&output, &crl)?;
+            println!("{product} CRL saved to {}", output.display());
+        }
+        Commands::Cache(command) => {
+            cache_command(&cli.backend, &cli.cache, &command)?;
+        }
+        Commands::Report(ReportCommand {
+            command:
+                Some(ReportSubcommand::Decode {
+                    input,
+                    format,
+                    product,
+                }),
+            ..
+        }) => {
+            decode_report(&input, format, product)?;
+        }
+        Commands::Report(ReportCommand {
+            command: None,
+            args,
+        }) => {
+            let mut fw = firmware::open(&cli.backend)?;
+            display_report(fw.as_mut(), &args)?;
+        }
+        Commands::DeriveKey(args) => {
+            let mut fw = firmware::open(&cli.backend)?;
+            derive_key(fw.as_mut(), &args)?;
+        }
+        Commands::Cert(CertCommand::Inspect { input }) => {
+            inspect_certs(&input)?;
+        }
+        Commands::Cert(CertCommand::Convert { inputs, output, to }) => {
+            convert_certs(&inputs, &output, to)?;
+        }
+        Commands::Verify(args) => {
+            verify_report(&cli.backend, &cli.cache, &cli.kds, &args).await?;
+        }
+        Commands::GenTestPki(args) => {
+            gen_test_pki(&args)?;
+        }
+        Commands::MockKds(args) => {
+            mock_kds::run(&args).await?;
         }
-        Commands::Report => {
-            display_report()?;
+        Commands::Measure(args) => {
+            measure::run(&args)?;
         }
     }
 
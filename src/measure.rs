@@ -0,0 +1,494 @@
+//! Prediction of the SNP launch digest (MEASUREMENT) of a QEMU guest.
+//!
+//! Every SNP_LAUNCH_UPDATE extends the digest with a PAGE_INFO structure,
+//! SEV-SNP ABI spec section 8.17: the current digest, the page contents (or
+//! their SHA-384), and the page type and GPA. This replays the updates QEMU
+//! performs for OVMF, the kernel hashes table and the vCPUs' VMSAs, or for
+//! the directives of an IGVM image.
+
+use anyhow::{bail, ensure, Context};
+use clap::Args;
+use openssl::sha::{sha256, sha384};
+use std::{fs, path::PathBuf};
+use uuid::Uuid;
+
+use crate::{
+    igvm::{IgvmMeasurement, Platform},
+    ovmf::{Ovmf, SectionKind},
+    report,
+    vmsa::{self, VcpuType, PAGE_SIZE},
+};
+
+const DIGEST_SIZE: usize = 48;
+const PAGE_INFO_SIZE: u16 = 0x70;
+/// The GPA KVM measures VMSAs at.
+pub const VMSA_GPA: u64 = 0xffff_ffff_f000;
+
+const HASH_TABLE_GUID: Uuid = uuid::uuid!("9438d606-4f22-4cc9-b479-a793d411fd21");
+const KERNEL_HASH_GUID: Uuid = uuid::uuid!("4de79437-abd2-427f-b835-d5b172d2045b");
+const INITRD_HASH_GUID: Uuid = uuid::uuid!("44baf731-3a2f-4bd7-9af1-41e29169781d");
+const CMDLINE_HASH_GUID: Uuid = uuid::uuid!("97d02dd8-bd20-4c94-aa78-e7714d36ab2a");
+
+/// PAGE_TYPE of an SNP_LAUNCH_UPDATE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PageType {
+    Normal = 1,
+    Vmsa = 2,
+    Zero = 3,
+    Unmeasured = 4,
+    Secrets = 5,
+    Cpuid = 6,
+}
+
+#[derive(Args)]
+pub struct MeasureArgs {
+    /// OVMF image built for SEV-SNP (e.g. OVMF.amdsev.fd for direct boot).
+    #[arg(long, required_unless_present = "igvm")]
+    pub ovmf: Option<PathBuf>,
+
+    /// IGVM image, e.g. COCONUT-SVSM, which carries its own vCPU state.
+    #[arg(
+        long,
+        conflicts_with_all = ["ovmf", "kernel", "vcpus", "vcpu_type", "vcpu_sig", "guest_features"]
+    )]
+    pub igvm: Option<PathBuf>,
+
+    /// Hypervisor that loads --igvm, which decides where VMSAs are measured.
+    #[arg(long, value_enum, default_value_t = Platform::Kvm, requires = "igvm")]
+    pub platform: Platform,
+
+    /// Kernel passed to QEMU with -kernel; its hash is measured with OVMF.
+    #[arg(long)]
+    pub kernel: Option<PathBuf>,
+
+    /// Initrd passed to QEMU with -initrd.
+    #[arg(long, requires = "kernel")]
+    pub initrd: Option<PathBuf>,
+
+    /// Kernel command line passed to QEMU with -append.
+    #[arg(long, requires = "kernel")]
+    pub append: Option<String>,
+
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub vcpus: u32,
+
+    /// QEMU CPU model of the guest's vCPUs.
+    #[arg(
+        long,
+        value_enum,
+        ignore_case = true,
+        required_unless_present_any = ["vcpu_sig", "igvm"]
+    )]
+    pub vcpu_type: Option<VcpuType>,
+
+    /// CPUID leaf 1 EAX signature of the vCPUs, e.g. 0xa00f11, instead of --vcpu-type.
+    #[arg(long, value_parser = parse_u32, conflicts_with = "vcpu_type")]
+    pub vcpu_sig: Option<u32>,
+
+    /// SEV_FEATURES of the VMSAs; bit 0 (SNPActive) must be set.
+    #[arg(long, value_parser = parse_u64, default_value = "0x1")]
+    pub guest_features: u64,
+
+    /// Raw attestation report to compare the computed digest, and any
+    /// policy and ID block fields from --igvm, with.
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+}
+
+/// The running launch digest.
+pub struct LaunchDigest([u8; DIGEST_SIZE]);
+
+impl LaunchDigest {
+    pub fn new() -> Self {
+        Self([0; DIGEST_SIZE])
+    }
+
+    pub fn digest(&self) -> [u8; DIGEST_SIZE] {
+        self.0
+    }
+
+    /// Extend the digest with one PAGE_INFO. `contents` is the SHA-384 of the
+    /// page for normal and VMSA pages, and zero for every other type.
+    pub fn update(&mut self, page_type: PageType, gpa: u64, contents: &[u8; DIGEST_SIZE]) {
+        let mut page_info = Vec::with_capacity(PAGE_INFO_SIZE.into());
+        page_info.extend_from_slice(&self.0);
+        page_info.extend_from_slice(contents);
+        page_info.extend_from_slice(&PAGE_INFO_SIZE.to_le_bytes());
+        page_info.push(page_type as u8);
+        // IMI_PAGE, the VMPL1-3 permissions and a reserved byte.
+        page_info.extend_from_slice(&[0; 5]);
+        page_info.extend_from_slice(&gpa.to_le_bytes());
+        self.0 = sha384(&page_info);
+    }
+
+    /// Measure `data` as normal pages from `gpa`, zero-padding the last page.
+    pub fn normal_pages(&mut self, gpa: u64, data: &[u8]) {
+        for (i, chunk) in data.chunks(PAGE_SIZE).enumerate() {
+            let mut page = [0u8; PAGE_SIZE];
+            page[..chunk.len()].copy_from_slice(chunk);
+            self.update(
+                PageType::Normal,
+                gpa + (i * PAGE_SIZE) as u64,
+                &sha384(&page),
+            );
+        }
+    }
+
+    /// Measure `size` bytes from `gpa` as pages of `page_type` with no contents.
+    pub fn empty_pages(&mut self, page_type: PageType, gpa: u64, size: u64) {
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            self.update(page_type, gpa + offset, &[0; DIGEST_SIZE]);
+        }
+    }
+
+    pub fn vmsa_page(&mut self, gpa: u64, page: &[u8; PAGE_SIZE]) {
+        self.update(PageType::Vmsa, gpa, &sha384(page));
+    }
+}
+
+/// The SHA-256 hashes of the kernel, initrd and command line that OVMF
+/// checks before booting them.
+struct KernelHashes {
+    kernel: [u8; 32],
+    initrd: [u8; 32],
+    cmdline: [u8; 32],
+}
+
+impl KernelHashes {
+    fn load(args: &MeasureArgs) -> anyhow::Result<Option<Self>> {
+        let Some(kernel) = &args.kernel else {
+            return Ok(None);
+        };
+        let read = |path: &PathBuf| {
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+        };
+        let initrd = args
+            .initrd
+            .as_ref()
+            .map(read)
+            .transpose()?
+            .unwrap_or_default();
+        // QEMU hashes the command line with its terminating NUL.
+        let mut cmdline = args.append.clone().unwrap_or_default().into_bytes();
+        cmdline.push(0);
+        Ok(Some(Self {
+            kernel: sha256(&read(kernel)?),
+            initrd: sha256(&initrd),
+            cmdline: sha256(&cmdline),
+        }))
+    }
+
+    /// The hashes table page, with the table at `offset`: a GUID and length
+    /// header followed by GUID, length and hash entries.
+    fn page(&self, offset: usize) -> [u8; PAGE_SIZE] {
+        const ENTRY_SIZE: u16 = 16 + 2 + 32;
+        let mut table = Vec::new();
+        table.extend_from_slice(&HASH_TABLE_GUID.to_bytes_le());
+        table.extend_from_slice(&(16 + 2 + 3 * ENTRY_SIZE).to_le_bytes());
+        for (guid, hash) in [
+            (CMDLINE_HASH_GUID, &self.cmdline),
+            (INITRD_HASH_GUID, &self.initrd),
+            (KERNEL_HASH_GUID, &self.kernel),
+        ] {
+            table.extend_from_slice(&guid.to_bytes_le());
+            table.extend_from_slice(&ENTRY_SIZE.to_le_bytes());
+            table.extend_from_slice(hash);
+        }
+        let mut page = [0u8; PAGE_SIZE];
+        page[offset..offset + table.len()].copy_from_slice(&table);
+        page
+    }
+}
+
+/// The launch digest QEMU produces for `args`.
+pub fn launch_digest(args: &MeasureArgs) -> anyhow::Result<[u8; DIGEST_SIZE]> {
+    ensure!(
+        args.guest_features & 1 != 0,
+        "--guest-features must set bit 0 (SNPActive)"
+    );
+    let signature = match (args.vcpu_type, args.vcpu_sig) {
+        (_, Some(signature)) => signature,
+        (Some(vcpu_type), None) => vcpu_type.signature(),
+        (None, None) => bail!("Pass --vcpu-type or --vcpu-sig"),
+    };
+    let ovmf = Ovmf::load(args.ovmf.as_deref().context("Pass --ovmf or --igvm")?)?;
+    let hashes = KernelHashes::load(args)?;
+    log::info!(
+        "OVMF is mapped at {:#x} with {} SEV metadata sections",
+        ovmf.gpa(),
+        ovmf.sections().len()
+    );
+
+    let mut digest = LaunchDigest::new();
+    digest.normal_pages(ovmf.gpa(), ovmf.data());
+
+    let mut hashes_measured = false;
+    for section in ovmf.sections() {
+        match (section.kind, &hashes) {
+            (SectionKind::Zero | SectionKind::SvsmCaa, _) | (SectionKind::KernelHashes, None) => {
+                digest.empty_pages(PageType::Zero, section.gpa, section.size)
+            }
+            (SectionKind::Secrets, _) => {
+                digest.empty_pages(PageType::Secrets, section.gpa, PAGE_SIZE as u64)
+            }
+            (SectionKind::Cpuid, _) => {
+                digest.empty_pages(PageType::Cpuid, section.gpa, PAGE_SIZE as u64)
+            }
+            (SectionKind::KernelHashes, Some(hashes)) => {
+                let table_gpa = ovmf.hashes_table_gpa()?;
+                ensure!(
+                    section.size == PAGE_SIZE as u64
+                        && (section.gpa..section.gpa + section.size).contains(&table_gpa),
+                    "OVMF hashes table at {table_gpa:#x} is not in its one-page metadata section"
+                );
+                let offset = (table_gpa - section.gpa) as usize;
+                digest.normal_pages(section.gpa, &hashes.page(offset));
+                hashes_measured = true;
+            }
+        }
+    }
+    ensure!(
+        hashes.is_none() || hashes_measured,
+        "OVMF has no kernel hashes section; use an OVMF built for direct boot (AmdSev)"
+    );
+
+    let ap_eip = match args.vcpus {
+        1 => 0,
+        _ => ovmf.reset_eip()?,
+    };
+    for page in vmsa::pages(args.vcpus, ap_eip, signature, args.guest_features) {
+        digest.vmsa_page(VMSA_GPA, &page);
+    }
+    Ok(digest.digest())
+}
+
+pub fn run(args: &MeasureArgs) -> anyhow::Result<()> {
+    let (measurement, igvm) = match &args.igvm {
+        Some(path) => {
+            let igvm = IgvmMeasurement::load(path, args.platform)?;
+            print!("{igvm}");
+            (igvm.measurement, Some(igvm))
+        }
+        None => {
+            let measurement = launch_digest(args)?;
+            println!("{}", hex::encode(measurement));
+            (measurement, None)
+        }
+    };
+
+    let Some(path) = &args.report else {
+        return Ok(());
+    };
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read report {}", path.display()))?;
+    let report = report::from_bytes(&bytes)?;
+
+    let mut mismatches = Vec::new();
+    let mut compare = |field: &str, expected: String, actual: String| {
+        if expected != actual {
+            mismatches.push(format!("{field} is {actual}, expected {expected}"));
+        }
+    };
+    compare(
+        "measurement",
+        hex::encode(measurement),
+        hex::encode(report.measurement),
+    );
+    if let Some(policy) = igvm.as_ref().and_then(|igvm| igvm.policy) {
+        let actual = report::policy(&report)?;
+        compare("policy", format!("{policy:#x}"), format!("{actual:#x}"));
+    }
+    if let Some(id_block) = igvm.as_ref().and_then(|igvm| igvm.id_block.as_ref()) {
+        compare(
+            "family_id",
+            hex::encode(id_block.family_id),
+            hex::encode(report.family_id),
+        );
+        compare(
+            "image_id",
+            hex::encode(id_block.image_id),
+            hex::encode(report.image_id),
+        );
+        compare(
+            "guest_svn",
+            id_block.guest_svn.to_string(),
+            report.guest_svn.to_string(),
+        );
+        compare(
+            "id_key_digest",
+            hex::encode(id_block.id_key_digest),
+            hex::encode(report.id_key_digest),
+        );
+        compare(
+            "author_key_digest",
+            hex::encode(id_block.author_key_digest),
+            hex::encode(report.author_key_digest),
+        );
+    }
+    match mismatches.as_slice() {
+        [] => println!("Report matches"),
+        [mismatch] => bail!("Report {mismatch}"),
+        _ => bail!("Report does not match:\n  {}", mismatches.join("\n  ")),
+    }
+    Ok(())
+}
+
+pub fn parse_u32(value: &str) -> anyhow::Result<u32> {
+    Ok(parse_u64(value)?.try_into()?)
+}
+
+/// Parse a decimal or 0x-prefixed hex integer.
+pub fn parse_u64(value: &str) -> anyhow::Result<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .with_context(|| format!("{value:?} is not a number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    const OVMF_SIZE: usize = 0x40000;
+    const METADATA_OFFSET: usize = 0x1000;
+    /// SEV metadata sections as (gpa, size, type): zero, secrets, CPUID,
+    /// kernel hashes and zero again.
+    const SECTIONS: [(u32, u32, u32); 5] = [
+        (0x80_0000, 0x9000, 1),
+        (0x80_9000, 0x1000, 2),
+        (0x80_a000, 0x1000, 3),
+        (0x80_b000, 0x1000, 0x10),
+        (0x80_c000, 0x4000, 1),
+    ];
+
+    /// A 256 KiB OVMF stand-in: a byte pattern with SEV metadata at 0x1000
+    /// and a footer table holding the metadata offset, an SEV-ES reset block
+    /// with AP eip 0xffff3000 and a hashes table at 0x80bc00.
+    fn synthetic_ovmf() -> Vec<u8> {
+        let mut data: Vec<u8> = (0..OVMF_SIZE).map(|i| (i * 7 + 3) as u8).collect();
+
+        let mut metadata = b"ASEV".to_vec();
+        for field in [16 + 12 * SECTIONS.len() as u32, 1, SECTIONS.len() as u32] {
+            metadata.extend_from_slice(&field.to_le_bytes());
+        }
+        for (gpa, size, kind) in SECTIONS {
+            for field in [gpa, size, kind] {
+                metadata.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+        data[METADATA_OFFSET..METADATA_OFFSET + metadata.len()].copy_from_slice(&metadata);
+
+        let entry = |guid: &str, payload: &[u8]| {
+            let size = (payload.len() + 18) as u16;
+            let guid = Uuid::parse_str(guid).unwrap().to_bytes_le();
+            [payload, &size.to_le_bytes(), &guid].concat()
+        };
+        let table = [
+            entry(
+                "dc886566-984a-4798-a75e-5585a7bf67cc",
+                &((OVMF_SIZE - METADATA_OFFSET) as u32).to_le_bytes(),
+            ),
+            entry(
+                "00f771de-1a7e-4fcb-890e-68c77e2fb44e",
+                &0xffff_3000u32.to_le_bytes(),
+            ),
+            entry(
+                "7255371f-3a3b-4b04-927b-1da6efa8d454",
+                &[0x80_bc00u32.to_le_bytes(), 0x400u32.to_le_bytes()].concat(),
+            ),
+        ]
+        .concat();
+        let footer = entry("96b582de-1fb2-45f7-baea-a366c55a082d", &table);
+        let end = OVMF_SIZE - 32;
+        data[end - footer.len()..end].copy_from_slice(&footer);
+        data
+    }
+
+    /// Write the image, a kernel and an initrd to a fresh directory.
+    fn guest_files(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        dir.write("ovmf.fd", synthetic_ovmf());
+        dir.write("kernel", b"kernel".repeat(1000));
+        dir.write("initrd", b"initrd".repeat(777));
+        dir
+    }
+
+    fn args(dir: &std::path::Path, vcpus: u32, vcpu_type: VcpuType, kernel: bool) -> MeasureArgs {
+        MeasureArgs {
+            ovmf: Some(dir.join("ovmf.fd")),
+            igvm: None,
+            platform: Platform::Kvm,
+            kernel: kernel.then(|| dir.join("kernel")),
+            initrd: kernel.then(|| dir.join("initrd")),
+            append: kernel.then(|| "console=ttyS0".to_string()),
+            vcpus,
+            vcpu_type: Some(vcpu_type),
+            vcpu_sig: None,
+            guest_features: 0x1,
+            report: None,
+        }
+    }
+
+    /// Digests this implementation produced for the files above, pinned so
+    /// that any change to the launch sequence shows up. They have not been
+    /// checked against another implementation.
+    #[test]
+    fn ovmf_regression_digests() {
+        let dir = guest_files("ovmf-kat");
+        let digest = |args: MeasureArgs| hex::encode(launch_digest(&args).unwrap());
+
+        let milan = args(dir.path(), 1, VcpuType::EpycMilan, false);
+        let genoa = MeasureArgs {
+            guest_features: 0x21,
+            ..args(dir.path(), 4, VcpuType::EpycGenoa, false)
+        };
+        let genoa_kernel = MeasureArgs {
+            guest_features: 0x21,
+            ..args(dir.path(), 4, VcpuType::EpycGenoa, true)
+        };
+        assert_eq!(
+            [digest(milan), digest(genoa), digest(genoa_kernel)],
+            [
+                "4772e8f86b522c8c3c0dfbbdabeaa99ab59fccc4c028fbd5ef410c0c8bfef5ac74add02c1fd0d8a140c219bcf3e907b1",
+                "8c6a30a3dd796988af1bcf8692bc66e9bcdc0504f7ecf010005ca4eb8511b810030e40f888fc0c3d2fc66eaabd5e19e8",
+                "c0888e2e6057f0d38724a7e72753348cdba8085bbd10c3563ebbb5011fec9c6a6a8b3180b398b306882ffeba89d4db2c",
+            ]
+        );
+    }
+
+    #[test]
+    fn page_info_layout() {
+        let mut digest = LaunchDigest::new();
+        let contents = [0xab; DIGEST_SIZE];
+        digest.update(PageType::Cpuid, 0x1122_3344_5566_7788, &contents);
+
+        let mut page_info = [0u8; 0x70];
+        page_info[0x30..0x60].copy_from_slice(&contents);
+        page_info[0x60..0x62].copy_from_slice(&[0x70, 0x00]);
+        page_info[0x62] = 6;
+        page_info[0x68..].copy_from_slice(&[0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(digest.digest(), sha384(&page_info));
+    }
+
+    #[test]
+    fn kernel_without_hashes_section() {
+        let dir = guest_files("ovmf-no-hashes");
+        let mut image = synthetic_ovmf();
+        // Turn the kernel hashes section into a zero section.
+        let kind = METADATA_OFFSET + 16 + 3 * 12 + 8;
+        image[kind] = 1;
+        dir.write("ovmf.fd", image);
+
+        assert!(launch_digest(&args(dir.path(), 1, VcpuType::EpycMilan, false)).is_ok());
+        let err = launch_digest(&args(dir.path(), 1, VcpuType::EpycMilan, true))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("OVMF has no kernel hashes section"),
+            "{err}"
+        );
+    }
+}
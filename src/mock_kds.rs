@@ -0,0 +1,332 @@
+//! A stand-in for AMD KDS that serves certificates from a local directory,
+//! such as one written by `gen-test-pki`.
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+use hyper::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use std::{
+    convert::Infallible,
+    fs,
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use crate::{certs, extensions::VcekExtensions, product::Product, tcb::Tcb};
+
+/// Files served as-is from the certificate directory.
+const CERT_CHAIN: &str = "cert_chain.pem";
+const CRL: &str = "crl.der";
+const VLEK_CERT_CHAIN: &str = "vlek_cert_chain.pem";
+const VLEK_CRL: &str = "vlek_crl.der";
+
+#[derive(Args)]
+pub struct MockKdsArgs {
+    /// Directory holding cert_chain.pem, crl.der, vlek_cert_chain.pem,
+    /// vlek_crl.der and any number of VCEKs.
+    #[arg(short, long, default_value = "test-pki")]
+    pub dir: PathBuf,
+
+    #[arg(long, default_value = "127.0.0.1:8089")]
+    pub listen: SocketAddr,
+
+    /// Product line served; requests for other products get 404.
+    #[arg(long, value_enum, default_value_t = Product::Genoa)]
+    pub product: Product,
+
+    /// Answer this many requests with 429 before serving normally.
+    #[arg(long, default_value_t = 0)]
+    pub inject_429: u32,
+
+    /// Retry-After value sent with injected 429 responses, in seconds.
+    #[arg(long, default_value_t = 1)]
+    pub retry_after: u32,
+
+    /// Then answer this many requests with 404.
+    #[arg(long, default_value_t = 0)]
+    pub inject_404: u32,
+}
+
+struct Vcek {
+    extensions: VcekExtensions,
+    der: Vec<u8>,
+}
+
+struct MockKds {
+    dir: PathBuf,
+    product: Product,
+    vceks: Vec<Vcek>,
+    inject_429: AtomicU32,
+    retry_after: u32,
+    inject_404: AtomicU32,
+}
+
+/// Serve until interrupted.
+pub async fn run(args: &MockKdsArgs) -> anyhow::Result<()> {
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let (addr, server) = bind(args, shutdown)?;
+    println!("Mock KDS listening on http://{addr}");
+    server.await
+}
+
+/// Load the certificates and bind the listening socket, which may be port 0.
+/// The returned future serves until `shutdown` completes.
+pub fn bind(
+    args: &MockKdsArgs,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<(SocketAddr, impl Future<Output = anyhow::Result<()>>)> {
+    let vceks = load_vceks(args)?;
+    println!(
+        "Serving {} {} VCEK(s) from {}",
+        vceks.len(),
+        args.product,
+        args.dir.display()
+    );
+
+    let kds = Arc::new(MockKds {
+        dir: args.dir.clone(),
+        product: args.product,
+        vceks,
+        inject_429: AtomicU32::new(args.inject_429),
+        retry_after: args.retry_after,
+        inject_404: AtomicU32::new(args.inject_404),
+    });
+
+    let make_service = make_service_fn(move |_| {
+        let kds = kds.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let kds = kds.clone();
+                async move { Ok::<_, Infallible>(kds.handle(&request)) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&args.listen)
+        .with_context(|| format!("Failed to listen on {}", args.listen))?
+        .serve(make_service);
+    let addr = server.local_addr();
+    let server = server.with_graceful_shutdown(shutdown);
+    Ok((addr, async { server.await.context("Mock KDS failed") }))
+}
+
+/// Every certificate in the directory that carries AMD VCEK extensions.
+fn load_vceks(args: &MockKdsArgs) -> anyhow::Result<Vec<Vcek>> {
+    let entries = fs::read_dir(&args.dir)
+        .with_context(|| format!("Failed to read {}", args.dir.display()))?;
+
+    let mut vceks = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_cert = path
+            .extension()
+            .is_some_and(|ext| ext == "der" || ext == "pem" || ext == "bin");
+        if !is_cert {
+            continue;
+        }
+        let Ok(cert) = certs::load_cert(&path) else {
+            continue;
+        };
+        let Ok(extensions) = VcekExtensions::from_cert(&cert) else {
+            continue;
+        };
+        let Some(hw_id) = &extensions.hw_id else {
+            continue;
+        };
+        log::info!(
+            "Loaded VCEK {} for chip {} at {}",
+            path.display(),
+            hex::encode(hw_id),
+            extensions.tcb()
+        );
+        vceks.push(Vcek {
+            extensions,
+            der: cert.to_der()?,
+        });
+    }
+    Ok(vceks)
+}
+
+impl MockKds {
+    fn handle(&self, request: &Request<Body>) -> Response<Body> {
+        let response = self.route(request);
+        log::info!(
+            "{} {} -> {}",
+            request.method(),
+            request.uri(),
+            response.status()
+        );
+        response
+    }
+
+    fn route(&self, request: &Request<Body>) -> Response<Body> {
+        if request.method() != Method::GET {
+            return text(StatusCode::METHOD_NOT_ALLOWED, "Only GET is supported");
+        }
+
+        if self.take(&self.inject_429) {
+            let mut response = text(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests");
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, self.retry_after.into());
+            return response;
+        }
+        if self.take(&self.inject_404) {
+            return text(StatusCode::NOT_FOUND, "Not Found (injected)");
+        }
+
+        let segments: Vec<&str> = request.uri().path().trim_matches('/').split('/').collect();
+        let (service, product, resource) = match segments[..] {
+            [service @ ("vcek" | "vlek"), "v1", product, resource] => (service, product, resource),
+            _ => return text(StatusCode::NOT_FOUND, "Not Found"),
+        };
+        match Product::from_str(product, true) {
+            Ok(product) if product == self.product => {}
+            _ => return text(StatusCode::NOT_FOUND, "Unknown product"),
+        }
+
+        match (service, resource) {
+            ("vcek", "cert_chain") => self.file(CERT_CHAIN, "application/x-pem-file"),
+            ("vcek", "crl") => self.file(CRL, "application/pkix-crl"),
+            ("vlek", "cert_chain") => self.file(VLEK_CERT_CHAIN, "application/x-pem-file"),
+            ("vlek", "crl") => self.file(VLEK_CRL, "application/pkix-crl"),
+            ("vcek", hw_id) => self.vcek(hw_id, request.uri().query().unwrap_or_default()),
+            _ => text(StatusCode::NOT_FOUND, "Not Found"),
+        }
+    }
+
+    /// Consume one injected failure from `counter`, if any are left.
+    fn take(&self, counter: &AtomicU32) -> bool {
+        counter
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    fn vcek(&self, hw_id: &str, query: &str) -> Response<Body> {
+        let hw_id = match hex::decode(hw_id) {
+            Ok(hw_id) if hw_id.len() == self.product.hw_id_len() => hw_id,
+            _ => return text(StatusCode::BAD_REQUEST, "Invalid hardware ID"),
+        };
+        let tcb = match Tcb::from_kds_query(self.product, query) {
+            Ok(tcb) => tcb,
+            Err(e) => return text(StatusCode::BAD_REQUEST, &format!("{e:#}")),
+        };
+
+        let mut for_chip = self
+            .vceks
+            .iter()
+            .filter(|vcek| vcek.extensions.hw_id.as_ref() == Some(&hw_id))
+            .peekable();
+        if for_chip.peek().is_none() {
+            return text(StatusCode::NOT_FOUND, "Unknown hardware ID");
+        }
+        match for_chip.find(|vcek| vcek.extensions.tcb() == tcb) {
+            Some(vcek) => bytes(vcek.der.clone(), "application/pkix-cert"),
+            None => text(
+                StatusCode::BAD_REQUEST,
+                &format!("No VCEK for this chip at {tcb}"),
+            ),
+        }
+    }
+
+    fn file(&self, name: &str, content_type: &str) -> Response<Body> {
+        match fs::read(self.dir.join(name)) {
+            Ok(contents) => bytes(contents, content_type),
+            Err(_) => text(StatusCode::NOT_FOUND, &format!("{name} not available")),
+        }
+    }
+}
+
+fn bytes(contents: Vec<u8>, content_type: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(contents));
+    if let Ok(content_type) = content_type.parse() {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    response
+}
+
+fn text(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = bytes(format!("{message}\n").into_bytes(), "text/plain");
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kds::{KdsArgs, KdsClient, KdsError},
+        pki::TestCa,
+        report::SigningKey,
+        testutil::TempDir,
+    };
+
+    /// Serve a fresh Genoa cert_chain.pem, rate limiting the first
+    /// `inject_429` requests. Returns a client allowed `retries` retries and
+    /// the directory served, which lives until the guard is dropped.
+    fn serve(name: &str, inject_429: u32, retries: u32) -> (KdsClient, TempDir) {
+        let dir = TempDir::new(name);
+        let ca = TestCa::generate(Product::Genoa).unwrap();
+        let chain = [ca.ask.to_pem().unwrap(), ca.ark.to_pem().unwrap()].concat();
+        dir.write(CERT_CHAIN, chain);
+
+        let args = MockKdsArgs {
+            dir: dir.path().to_path_buf(),
+            listen: "127.0.0.1:0".parse().unwrap(),
+            product: Product::Genoa,
+            inject_429,
+            retry_after: 0,
+            inject_404: 0,
+        };
+        let (addr, server) = bind(&args, std::future::pending()).unwrap();
+        tokio::spawn(server);
+
+        let kds = KdsClient::new(&KdsArgs {
+            kds_url: format!("http://{addr}"),
+            kds_proxy: None,
+            kds_ca_cert: None,
+            kds_timeout: 10,
+            kds_retries: retries,
+        })
+        .unwrap();
+        (kds, dir)
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limit() {
+        let (kds, _dir) = serve("retry", 2, 2);
+        let chain = kds
+            .cert_chain(Product::Genoa, SigningKey::Vcek)
+            .await
+            .unwrap();
+        certs::split_cert_chain(&chain).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_still_rate_limited() {
+        let (kds, _dir) = serve("give-up", 3, 2);
+        let err = kds
+            .cert_chain(Product::Genoa, SigningKey::Vcek)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<KdsError>(),
+                Some(KdsError::RateLimited {
+                    retry_after: Some(delay)
+                }) if delay.is_zero()
+            ),
+            "{err:#}"
+        );
+    }
+}
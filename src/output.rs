@@ -0,0 +1,59 @@
+//! Machine-readable output of reports and verification results.
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned, human-readable text.
+    #[default]
+    Text,
+    /// JSON following schema/*.schema.json.
+    Json,
+    /// YAML with the same structure as the JSON output.
+    Yaml,
+    /// Binary CBOR with the same structure as the JSON output.
+    Cbor,
+}
+
+impl OutputFormat {
+    pub fn is_text(self) -> bool {
+        self == OutputFormat::Text
+    }
+}
+
+/// Print `value` to stdout in `format`, falling back to its Display
+/// implementation for text.
+pub fn emit<T: Serialize + fmt::Display>(format: OutputFormat, value: &T) -> anyhow::Result<()> {
+    let mut stdout = io::stdout().lock();
+    match format {
+        OutputFormat::Text => write!(stdout, "{value}")?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, value).context("Failed to encode JSON")?;
+            writeln!(stdout)?;
+        }
+        OutputFormat::Yaml => {
+            serde_yaml::to_writer(&mut stdout, value).context("Failed to encode YAML")?
+        }
+        OutputFormat::Cbor => {
+            ciborium::into_writer(value, &mut stdout).context("Failed to encode CBOR")?
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Print a progress or status line: to stdout with text output, otherwise to
+/// stderr so it does not corrupt the structured document.
+pub fn status(format: OutputFormat, message: impl fmt::Display) {
+    if format.is_text() {
+        println!("{message}");
+    } else {
+        eprintln!("{message}");
+    }
+}
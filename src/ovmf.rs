@@ -0,0 +1,203 @@
+//! The parts of an OVMF image that determine its SNP launch measurement.
+//!
+//! OVMF ends with a GUIDed table just below the reset vector. Each entry is
+//! `data, u16 size, GUID` and is read backwards from the footer entry, 32
+//! bytes before the end of the image. The SEV metadata entry points at a list
+//! of memory sections the hypervisor prepares before launch.
+
+use anyhow::{bail, ensure, Context};
+use std::{fs, path::Path};
+use uuid::Uuid;
+
+const FOUR_GB: u64 = 0x1_0000_0000;
+const FOOTER_GUID: Uuid = uuid::uuid!("96b582de-1fb2-45f7-baea-a366c55a082d");
+const SEV_HASH_TABLE_RV_GUID: Uuid = uuid::uuid!("7255371f-3a3b-4b04-927b-1da6efa8d454");
+const SEV_ES_RESET_BLOCK_GUID: Uuid = uuid::uuid!("00f771de-1a7e-4fcb-890e-68c77e2fb44e");
+const SEV_METADATA_GUID: Uuid = uuid::uuid!("dc886566-984a-4798-a75e-5585a7bf67cc");
+
+/// `u16 size` followed by a GUID.
+const ENTRY_HEADER_SIZE: usize = 18;
+const METADATA_SIGNATURE: &[u8; 4] = b"ASEV";
+const METADATA_HEADER_SIZE: usize = 16;
+const METADATA_SECTION_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    /// Pre-validated zero memory.
+    Zero,
+    Secrets,
+    Cpuid,
+    /// The SVSM calling area, zeroed.
+    SvsmCaa,
+    /// The page holding the kernel, initrd and cmdline hashes table.
+    KernelHashes,
+}
+
+#[derive(Debug)]
+pub struct Section {
+    pub gpa: u64,
+    pub size: u64,
+    pub kind: SectionKind,
+}
+
+pub struct Ovmf {
+    data: Vec<u8>,
+    table: Vec<(Uuid, Vec<u8>)>,
+    sections: Vec<Section>,
+}
+
+impl Ovmf {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data =
+            fs::read(path).with_context(|| format!("Failed to read OVMF {}", path.display()))?;
+        Self::parse(data).with_context(|| format!("Invalid OVMF image {}", path.display()))
+    }
+
+    fn parse(data: Vec<u8>) -> anyhow::Result<Self> {
+        ensure!(
+            !data.is_empty() && data.len().is_multiple_of(4096),
+            "size {} is not a whole number of pages",
+            data.len()
+        );
+        ensure!(
+            data.len() as u64 <= FOUR_GB,
+            "size {} does not fit below 4 GB",
+            data.len()
+        );
+        let mut ovmf = Self {
+            table: footer_table(&data)?,
+            data,
+            sections: Vec::new(),
+        };
+        ovmf.sections = ovmf.metadata()?;
+        Ok(ovmf)
+    }
+
+    /// The image is mapped to end at 4 GB.
+    pub fn gpa(&self) -> u64 {
+        FOUR_GB - self.data.len() as u64
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    /// Where application processors start, from the SEV-ES reset block.
+    pub fn reset_eip(&self) -> anyhow::Result<u32> {
+        self.entry_u32(SEV_ES_RESET_BLOCK_GUID)
+            .context("OVMF has no SEV-ES reset block")
+    }
+
+    /// The GPA of the kernel hashes table QEMU fills in.
+    pub fn hashes_table_gpa(&self) -> anyhow::Result<u64> {
+        self.entry_u32(SEV_HASH_TABLE_RV_GUID)
+            .map(u64::from)
+            .context("OVMF has no SEV hashes table entry, it cannot boot a measured kernel")
+    }
+
+    fn entry_u32(&self, guid: Uuid) -> anyhow::Result<u32> {
+        let (_, data) = self
+            .table
+            .iter()
+            .find(|(g, _)| *g == guid)
+            .with_context(|| format!("no {guid} entry in the footer table"))?;
+        let bytes = data
+            .get(..4)
+            .with_context(|| format!("{guid} entry is too short"))?;
+        Ok(u32::from_le_bytes(bytes.try_into()?))
+    }
+
+    /// The sections listed by the SEV metadata, which OVMF for SNP must carry.
+    fn metadata(&self) -> anyhow::Result<Vec<Section>> {
+        let offset = self
+            .entry_u32(SEV_METADATA_GUID)
+            .context("OVMF has no SEV metadata, it was not built for SEV-SNP")?;
+        let start = self
+            .data
+            .len()
+            .checked_sub(offset as usize)
+            .context("SEV metadata offset is outside the image")?;
+        let header = self
+            .data
+            .get(start..start + METADATA_HEADER_SIZE)
+            .context("SEV metadata header is outside the image")?;
+        let field = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        ensure!(
+            &header[..4] == METADATA_SIGNATURE,
+            "SEV metadata has a bad signature"
+        );
+        ensure!(
+            field(2) == 1,
+            "SEV metadata version {} is not supported",
+            field(2)
+        );
+        let count = field(3) as usize;
+        let items = self
+            .data
+            .get(start + METADATA_HEADER_SIZE..start + field(1) as usize)
+            .filter(|items| items.len() >= count * METADATA_SECTION_SIZE)
+            .context("SEV metadata sections are outside the image")?;
+
+        items
+            .chunks_exact(METADATA_SECTION_SIZE)
+            .take(count)
+            .map(|item| {
+                let field =
+                    |i: usize| u32::from_le_bytes(item[i * 4..i * 4 + 4].try_into().unwrap());
+                let kind = match field(2) {
+                    1 => SectionKind::Zero,
+                    2 => SectionKind::Secrets,
+                    3 => SectionKind::Cpuid,
+                    4 => SectionKind::SvsmCaa,
+                    0x10 => SectionKind::KernelHashes,
+                    kind => bail!("unknown SEV metadata section type {kind:#x}"),
+                };
+                Ok(Section {
+                    gpa: field(0).into(),
+                    size: field(1).into(),
+                    kind,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The GUIDed entries of the table ending 32 bytes before the end of `data`.
+fn footer_table(data: &[u8]) -> anyhow::Result<Vec<(Uuid, Vec<u8>)>> {
+    let footer_start = data
+        .len()
+        .checked_sub(32 + ENTRY_HEADER_SIZE)
+        .context("image is too small to hold a footer table")?;
+    let (size, guid) = entry_header(&data[footer_start..footer_start + ENTRY_HEADER_SIZE]);
+    ensure!(
+        guid == FOOTER_GUID,
+        "no OVMF footer table before the reset vector"
+    );
+    let mut table = data
+        .get(footer_start.saturating_sub(size.saturating_sub(ENTRY_HEADER_SIZE))..footer_start)
+        .filter(|_| size >= ENTRY_HEADER_SIZE && size - ENTRY_HEADER_SIZE <= footer_start)
+        .context("footer table size is invalid")?;
+
+    let mut entries = Vec::new();
+    while table.len() >= ENTRY_HEADER_SIZE {
+        let (size, guid) = entry_header(&table[table.len() - ENTRY_HEADER_SIZE..]);
+        ensure!(
+            (ENTRY_HEADER_SIZE..=table.len()).contains(&size),
+            "footer table entry {guid} has invalid size {size}"
+        );
+        let entry = &table[table.len() - size..];
+        entries.push((guid, entry[..size - ENTRY_HEADER_SIZE].to_vec()));
+        table = &table[..table.len() - size];
+    }
+    Ok(entries)
+}
+
+fn entry_header(bytes: &[u8]) -> (usize, Uuid) {
+    let size = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let guid = Uuid::from_bytes_le(bytes[2..ENTRY_HEADER_SIZE].try_into().unwrap());
+    (size.into(), guid)
+}
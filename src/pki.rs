@@ -0,0 +1,273 @@
+use anyhow::{bail, Context};
+use foreign_types::ForeignType;
+use openssl::{
+    asn1::{Asn1Integer, Asn1Object, Asn1OctetString, Asn1Time},
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    md::Md,
+    md_ctx::MdCtx,
+    nid::Nid,
+    pkey::{PKey, PKeyRef, Private},
+    rsa::{Padding, Rsa},
+    sign::RsaPssSaltlen,
+    x509::{
+        extension::{BasicConstraints, KeyUsage, SubjectKeyIdentifier},
+        X509Builder, X509Crl, X509Extension, X509Name, X509NameRef, X509,
+    },
+};
+use std::{
+    os::raw::c_int,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{der, extensions::VcekExtensions, product::Product};
+
+extern "C" {
+    // Not wrapped by the openssl crate; needed to sign with RSA-PSS.
+    fn X509_sign_ctx(x: *mut openssl_sys::X509, ctx: *mut openssl_sys::EVP_MD_CTX) -> c_int;
+}
+
+const ARK_VALIDITY_DAYS: u32 = 25 * 365;
+const VCEK_VALIDITY_DAYS: u32 = 7 * 365;
+
+/// A test ARK and ASK with their private keys.
+pub struct TestCa {
+    pub product: Product,
+    pub ark: X509,
+    pub ark_key: PKey<Private>,
+    pub ask: X509,
+    pub ask_key: PKey<Private>,
+}
+
+/// Configure `ctx` for RSASSA-PSS with SHA-384, MGF1-SHA-384 and a 48 byte
+/// salt, the parameters of every AMD ARK and ASK signature.
+pub fn pss_sign_init(ctx: &mut MdCtx, key: &PKeyRef<Private>) -> anyhow::Result<()> {
+    let pkey_ctx = ctx.digest_sign_init(Some(Md::sha384()), key)?;
+    pkey_ctx.set_rsa_padding(Padding::PKCS1_PSS)?;
+    pkey_ctx.set_rsa_pss_saltlen(RsaPssSaltlen::custom(48))?;
+    pkey_ctx.set_rsa_mgf1_md(Md::sha384())?;
+    Ok(())
+}
+
+fn sign_pss(cert: &mut X509, key: &PKeyRef<Private>) -> anyhow::Result<()> {
+    let mut ctx = MdCtx::new()?;
+    pss_sign_init(&mut ctx, key)?;
+
+    // SAFETY: both pointers are valid, owned handles for the duration of the
+    // call, and X509_sign_ctx only updates the certificate's signature fields.
+    let ret = unsafe { X509_sign_ctx(cert.as_ptr(), ctx.as_ptr()) };
+    if ret <= 0 {
+        bail!(
+            "Failed to sign certificate: {}",
+            openssl::error::ErrorStack::get()
+        );
+    }
+
+    Ok(())
+}
+
+fn amd_name(common_name: &str) -> anyhow::Result<X509Name> {
+    let mut name = X509Name::builder()?;
+    name.append_entry_by_nid(Nid::ORGANIZATIONALUNITNAME, "Engineering")?;
+    name.append_entry_by_nid(Nid::COUNTRYNAME, "US")?;
+    name.append_entry_by_nid(Nid::LOCALITYNAME, "Santa Clara")?;
+    name.append_entry_by_nid(Nid::STATEORPROVINCENAME, "CA")?;
+    name.append_entry_by_nid(Nid::ORGANIZATIONNAME, "Advanced Micro Devices")?;
+    name.append_entry_by_nid(Nid::COMMONNAME, common_name)?;
+    Ok(name.build())
+}
+
+fn crl_distribution_point(url: &str) -> anyhow::Result<X509Extension> {
+    // CRLDistributionPoints ::= SEQUENCE OF DistributionPoint, holding one
+    // fullName with a single uniformResourceIdentifier.
+    let uri = der::tlv(0x86, url.as_bytes());
+    let point = der::tlv(0xa0, &der::tlv(0xa0, &uri));
+    let der = der::tlv(der::TAG_SEQUENCE, &der::tlv(der::TAG_SEQUENCE, &point));
+
+    let oid = Asn1Object::from_str("2.5.29.31")?;
+    let value = Asn1OctetString::new_from_bytes(&der)?;
+    Ok(X509Extension::new_from_der(&oid, false, &value)?)
+}
+
+fn builder(
+    serial: u32,
+    subject: &X509NameRef,
+    issuer: &X509NameRef,
+    key: &PKeyRef<impl openssl::pkey::HasPublic>,
+    days: u32,
+) -> anyhow::Result<X509Builder> {
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let serial = BigNum::from_u32(serial)?;
+    builder.set_serial_number(Asn1Integer::from_bn(&serial)?.as_ref())?;
+    builder.set_subject_name(subject)?;
+    builder.set_issuer_name(issuer)?;
+    builder.set_pubkey(key)?;
+    builder.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+    builder.set_not_after(Asn1Time::days_from_now(days)?.as_ref())?;
+    Ok(builder)
+}
+
+fn ca_cert(
+    product: Product,
+    serial: u32,
+    subject: &X509NameRef,
+    issuer: &X509NameRef,
+    key: &PKey<Private>,
+    signer: &PKey<Private>,
+) -> anyhow::Result<X509> {
+    let mut builder = builder(serial, subject, issuer, key, ARK_VALIDITY_DAYS)?;
+    builder.append_extension(
+        KeyUsage::new()
+            .critical()
+            .key_cert_sign()
+            .crl_sign()
+            .build()?,
+    )?;
+    let ski = SubjectKeyIdentifier::new().build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(ski)?;
+    builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+    builder.append_extension(crl_distribution_point(&format!(
+        "https://kdsintf.amd.com/vcek/v1/{product}/crl"
+    ))?)?;
+
+    let mut cert = builder.build();
+    sign_pss(&mut cert, signer)?;
+    Ok(cert)
+}
+
+fn issue_key(
+    issuer: &X509,
+    issuer_key: &PKey<Private>,
+    common_name: &str,
+    extensions: &VcekExtensions,
+) -> anyhow::Result<(X509, EcKey<Private>)> {
+    let group = EcGroup::from_curve_name(Nid::SECP384R1)?;
+    let ec_key = EcKey::generate(&group)?;
+    let key = PKey::from_ec_key(ec_key.clone())?;
+
+    let name = amd_name(common_name)?;
+    let mut builder = builder(0, &name, issuer.subject_name(), &key, VCEK_VALIDITY_DAYS)?;
+    for extension in extensions.to_x509()? {
+        builder.append_extension(extension)?;
+    }
+
+    let mut cert = builder.build();
+    sign_pss(&mut cert, issuer_key)?;
+    Ok((cert, ec_key))
+}
+
+impl TestCa {
+    /// Generate a self-signed ARK and an ASK issued by it.
+    pub fn generate(product: Product) -> anyhow::Result<Self> {
+        let ark_key = PKey::from_rsa(Rsa::generate(4096)?)?;
+        let ask_key = PKey::from_rsa(Rsa::generate(4096)?)?;
+
+        let ark_name = amd_name(&format!("ARK-{product}"))?;
+        let ask_name = amd_name(&format!("SEV-{product}"))?;
+
+        let ark = ca_cert(product, 0x10000, &ark_name, &ark_name, &ark_key, &ark_key)
+            .context("Failed to create ARK")?;
+        let ask = ca_cert(product, 0x10001, &ask_name, &ark_name, &ask_key, &ark_key)
+            .context("Failed to create ASK")?;
+
+        Ok(Self {
+            product,
+            ark,
+            ark_key,
+            ask,
+            ask_key,
+        })
+    }
+
+    /// Generate an ASVK, the ARK-signed intermediate that issues VLEKs.
+    pub fn generate_asvk(&self) -> anyhow::Result<(X509, PKey<Private>)> {
+        let asvk_key = PKey::from_rsa(Rsa::generate(4096)?)?;
+        let asvk_name = amd_name(&format!("SEV-VLEK-{}", self.product))?;
+        let asvk = ca_cert(
+            self.product,
+            0x10002,
+            &asvk_name,
+            self.ark.subject_name(),
+            &asvk_key,
+            &self.ark_key,
+        )
+        .context("Failed to create ASVK")?;
+        Ok((asvk, asvk_key))
+    }
+
+    /// Issue a VCEK carrying `extensions`, with a fresh P-384 key.
+    pub fn issue_vcek(
+        &self,
+        extensions: &VcekExtensions,
+    ) -> anyhow::Result<(X509, EcKey<Private>)> {
+        issue_key(&self.ask, &self.ask_key, "SEV-VCEK", extensions).context("Failed to create VCEK")
+    }
+
+    /// Issue a VLEK from `asvk` carrying `extensions`, with a fresh P-384 key.
+    pub fn issue_vlek(
+        &self,
+        asvk: &X509,
+        asvk_key: &PKey<Private>,
+        extensions: &VcekExtensions,
+    ) -> anyhow::Result<(X509, EcKey<Private>)> {
+        issue_key(asvk, asvk_key, "SEV-VLEK", extensions).context("Failed to create VLEK")
+    }
+
+    /// Issue an ARK-signed CRL listing `revoked` serials, valid from now
+    /// until `next_update_days` from now; a negative value makes it stale.
+    pub fn crl(&self, revoked: &[BigNum], next_update_days: i64) -> anyhow::Result<X509Crl> {
+        self.issue_crl(&self.ark, &self.ark_key, revoked, next_update_days)
+    }
+
+    /// Like `crl`, but issued by `issuer`, e.g. the ASK to revoke VCEKs.
+    pub fn issue_crl(
+        &self,
+        issuer: &X509,
+        issuer_key: &PKey<Private>,
+        revoked: &[BigNum],
+        next_update_days: i64,
+    ) -> anyhow::Result<X509Crl> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let next_update = now + next_update_days * 24 * 60 * 60;
+
+        // Reuse the ARK's own RSASSA-PSS AlgorithmIdentifier.
+        let ark_der = self.ark.to_der()?;
+        let (ark, _) = der::expect(&ark_der, der::TAG_SEQUENCE)?;
+        let (_, rest) = der::expect(ark.value, der::TAG_SEQUENCE)?;
+        let (algorithm, _) = der::expect(rest, der::TAG_SEQUENCE)?;
+
+        let mut tbs = [
+            der::integer(1),
+            algorithm.raw.to_vec(),
+            issuer.subject_name().to_der()?,
+            der::time(now),
+            der::time(next_update),
+        ]
+        .concat();
+        if !revoked.is_empty() {
+            let entries: Vec<u8> = revoked
+                .iter()
+                .flat_map(|serial| {
+                    let entry = [der::unsigned_integer(&serial.to_vec()), der::time(now)].concat();
+                    der::tlv(der::TAG_SEQUENCE, &entry)
+                })
+                .collect();
+            tbs.extend(der::tlv(der::TAG_SEQUENCE, &entries));
+        }
+        let tbs = der::tlv(der::TAG_SEQUENCE, &tbs);
+
+        let mut ctx = MdCtx::new()?;
+        pss_sign_init(&mut ctx, issuer_key)?;
+        let mut signature = vec![0];
+        ctx.digest_sign_to_vec(&tbs, &mut signature)?;
+
+        let crl = [
+            tbs,
+            algorithm.raw.to_vec(),
+            der::tlv(der::TAG_BIT_STRING, &signature),
+        ]
+        .concat();
+        X509Crl::from_der(&der::tlv(der::TAG_SEQUENCE, &crl)).context("Failed to create CRL")
+    }
+}
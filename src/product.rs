@@ -0,0 +1,218 @@
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sev::firmware::guest::AttestationReport;
+use std::{fmt, fs};
+
+use crate::report;
+
+/// AMD EPYC product lines with SEV-SNP support.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ValueEnum, Deserialize, Serialize)]
+pub enum Product {
+    Milan,
+    Genoa,
+    Turin,
+}
+
+impl Product {
+    /// Map a CPUID family and model to its product line. Siena and Bergamo
+    /// parts are Genoa derivatives and share its certificates.
+    pub fn from_cpuid(family: u8, model: u8) -> Option<Self> {
+        match (family, model) {
+            (0x19, 0x00..=0x0f) => Some(Product::Milan),
+            (0x19, 0x10..=0x1f) | (0x19, 0xa0..=0xaf) => Some(Product::Genoa),
+            (0x1a, 0x00..=0x1f) => Some(Product::Turin),
+            _ => None,
+        }
+    }
+
+    /// A representative CPUID family and model for this product line.
+    pub fn cpuid(self) -> (u8, u8) {
+        match self {
+            Product::Milan => (0x19, 0x01),
+            Product::Genoa => (0x19, 0x11),
+            Product::Turin => (0x1a, 0x02),
+        }
+    }
+
+    /// Name used by AMD in certificate subjects and KDS paths.
+    pub fn name(self) -> &'static str {
+        match self {
+            Product::Milan => "Milan",
+            Product::Genoa => "Genoa",
+            Product::Turin => "Turin",
+        }
+    }
+
+    /// Number of chip ID bytes that identify a chip on this product line.
+    pub fn hw_id_len(self) -> usize {
+        match self {
+            Product::Turin => 8,
+            Product::Milan | Product::Genoa => 64,
+        }
+    }
+
+    /// Value of the VCEK productName extension, including the stepping.
+    pub fn vcek_product_name(self) -> &'static str {
+        match self {
+            Product::Milan => "Milan-B0",
+            Product::Genoa => "Genoa-B0",
+            Product::Turin => "Turin-B0",
+        }
+    }
+}
+
+impl fmt::Display for Product {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Pick the product line: an explicit override wins, then the CPUID fields of
+/// a version 3+ report, then the host's /proc/cpuinfo.
+pub fn detect(
+    override_product: Option<Product>,
+    report: Option<&AttestationReport>,
+) -> anyhow::Result<Product> {
+    if let Some(product) = override_product {
+        return Ok(product);
+    }
+
+    if let Some(report) = report {
+        if let Some((family, model, _)) = report::cpuid(report)? {
+            let product = Product::from_cpuid(family, model).with_context(|| {
+                format!(
+                    "Unknown product for CPUID family {family:#x} model {model:#x}, pass --product"
+                )
+            })?;
+            log::info!("Detected {product} from the report CPUID fields");
+            return Ok(product);
+        }
+    }
+
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo")
+        .context("Cannot detect the product without /proc/cpuinfo, pass --product")?;
+    let (family, model) =
+        parse_cpuinfo(&cpuinfo).context("No CPU family/model in /proc/cpuinfo, pass --product")?;
+    let product = Product::from_cpuid(family, model).with_context(|| {
+        format!("Host CPU family {family:#x} model {model:#x} is not an SEV-SNP product, pass --product")
+    })?;
+    log::info!("Detected {product} from /proc/cpuinfo");
+    Ok(product)
+}
+
+/// Pick the product line of a report that may come from another host: an
+/// explicit override wins, then the report's CPUID fields. Version 2 reports
+/// have none, so they need the override.
+pub fn for_report(
+    report: &AttestationReport,
+    override_product: Option<Product>,
+) -> anyhow::Result<Product> {
+    if override_product.is_none() && report::cpuid(report)?.is_none() {
+        bail!(
+            "Version {} reports do not record the CPU model, pass --product",
+            report.version
+        );
+    }
+    detect(override_product, Some(report))
+}
+
+fn parse_cpuinfo(cpuinfo: &str) -> Option<(u8, u8)> {
+    let field = |name: &str| {
+        cpuinfo.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == name).then(|| value.trim().parse::<u8>().ok())?
+        })
+    };
+    Some((field("cpu family")?, field("model")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(version: u32, family: u8, model: u8) -> AttestationReport {
+        let mut bytes = vec![0u8; report::REPORT_SIZE];
+        bytes[..4].copy_from_slice(&version.to_le_bytes());
+        bytes[report::CPUID_OFFSET] = family;
+        bytes[report::CPUID_OFFSET + 1] = model;
+        report::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn product_for_report() {
+        let genoa = report(3, 0x19, 0x11);
+        assert_eq!(for_report(&genoa, None).unwrap(), Product::Genoa);
+        assert_eq!(
+            for_report(&genoa, Some(Product::Milan)).unwrap(),
+            Product::Milan
+        );
+
+        let v2 = report(2, 0x19, 0x11);
+        assert_eq!(
+            for_report(&v2, None).unwrap_err().to_string(),
+            "Version 2 reports do not record the CPU model, pass --product"
+        );
+        assert_eq!(
+            for_report(&v2, Some(Product::Turin)).unwrap(),
+            Product::Turin
+        );
+
+        let unknown = report(5, 0x17, 0x31);
+        assert_eq!(
+            for_report(&unknown, None).unwrap_err().to_string(),
+            "Unknown product for CPUID family 0x17 model 0x31, pass --product"
+        );
+    }
+
+    #[test]
+    fn cpuid_models() {
+        let cases = [
+            (0x19, 0x00, Some(Product::Milan)),
+            (0x19, 0x01, Some(Product::Milan)),
+            (0x19, 0x0f, Some(Product::Milan)),
+            (0x19, 0x10, Some(Product::Genoa)),
+            (0x19, 0x11, Some(Product::Genoa)),
+            (0x19, 0x1f, Some(Product::Genoa)),
+            (0x19, 0xa0, Some(Product::Genoa)),
+            (0x19, 0xaf, Some(Product::Genoa)),
+            (0x1a, 0x00, Some(Product::Turin)),
+            (0x1a, 0x02, Some(Product::Turin)),
+            (0x1a, 0x1f, Some(Product::Turin)),
+            (0x19, 0x20, None),
+            (0x19, 0x9f, None),
+            (0x19, 0xb0, None),
+            (0x1a, 0x20, None),
+            (0x17, 0x31, None),
+        ];
+        for (family, model, product) in cases {
+            assert_eq!(
+                Product::from_cpuid(family, model),
+                product,
+                "family {family:#x} model {model:#x}"
+            );
+        }
+        for product in [Product::Milan, Product::Genoa, Product::Turin] {
+            let (family, model) = product.cpuid();
+            assert_eq!(Product::from_cpuid(family, model), Some(product));
+        }
+    }
+
+    #[test]
+    fn cpuinfo() {
+        let cases = [
+            (
+                "processor\t: 0\nvendor_id\t: AuthenticAMD\ncpu family\t: 25\nmodel\t\t: 17\nmodel name\t: AMD EPYC 9654 96-Core Processor\n",
+                Some((0x19, 0x11)),
+            ),
+            ("cpu family\t: 26\nmodel\t\t: 2\n", Some((0x1a, 0x02))),
+            ("model name\t: AMD EPYC\nmodel\t\t: 1\ncpu family\t: 25\n", Some((0x19, 0x01))),
+            ("cpu family\t: 25\n", None),
+            ("cpu family\t: 25\nmodel\t\t: 0x11\n", None),
+            ("", None),
+        ];
+        for (cpuinfo, expected) in cases {
+            assert_eq!(parse_cpuinfo(cpuinfo), expected, "{cpuinfo:?}");
+        }
+    }
+}
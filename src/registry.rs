@@ -0,0 +1,360 @@
+//! A local registry of approved launch measurements.
+//!
+//! Registry files are TOML or JSON lists of named images; a directory is read
+//! as the union of every .toml and .json file in it:
+//!
+//! ```toml
+//! [[image]]
+//! name = "web-frontend-2024.06"
+//! description = "OVMF 2024.02 + frontend kernel 6.6.30"
+//! measurement = "5f2a...e1"          # 48 bytes of hex
+//! host_data = "0000...00"            # optional, 32 bytes of hex
+//! not_before = "2024-06-01"          # optional, or 2024-06-01T12:00:00Z
+//! not_after = "2025-06-01"           # optional
+//! revoked = false                    # optional
+//! ```
+
+use anyhow::{bail, ensure, Context};
+use openssl::asn1::Asn1Time;
+use serde::Deserialize;
+use sev::firmware::guest::AttestationReport;
+use std::{fs, path::Path};
+
+use crate::config::load_config;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    #[serde(default)]
+    image: Vec<ImageEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImageEntry {
+    name: String,
+    #[serde(default)]
+    description: String,
+    measurement: String,
+    host_data: Option<String>,
+    not_before: Option<String>,
+    not_after: Option<String>,
+    #[serde(default)]
+    revoked: bool,
+}
+
+/// An approved launch digest, optionally tied to the host data it runs with.
+pub struct Image {
+    pub name: String,
+    pub description: String,
+    measurement: Vec<u8>,
+    host_data: Option<Vec<u8>>,
+    not_before: Option<Asn1Time>,
+    not_after: Option<Asn1Time>,
+    revoked: bool,
+}
+
+pub struct Registry {
+    images: Vec<Image>,
+}
+
+impl Registry {
+    /// Load a registry file, or every .toml and .json file in a directory.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut files = Vec::new();
+        if path.is_dir() {
+            let entries = fs::read_dir(path)
+                .with_context(|| format!("Failed to read registry {}", path.display()))?;
+            for entry in entries {
+                let file = entry?.path();
+                if file
+                    .extension()
+                    .is_some_and(|ext| ext == "toml" || ext == "json")
+                {
+                    files.push(file);
+                }
+            }
+            files.sort();
+        } else {
+            files.push(path.to_path_buf());
+        }
+
+        let mut images: Vec<Image> = Vec::new();
+        for file in files {
+            for entry in load_file(&file)? {
+                let image = Image::from_entry(entry)
+                    .with_context(|| format!("Invalid registry entry in {}", file.display()))?;
+                if images.iter().any(|other| other.name == image.name) {
+                    bail!("Registry image {} is defined more than once", image.name);
+                }
+                images.push(image);
+            }
+        }
+        log::info!(
+            "Loaded {} registry images from {}",
+            images.len(),
+            path.display()
+        );
+        Ok(Self { images })
+    }
+
+    /// The image `report` was launched from. Fails if there is none, or if
+    /// every matching entry is revoked or outside its validity window.
+    pub fn find(&self, report: &AttestationReport) -> anyhow::Result<&Image> {
+        let candidates: Vec<&Image> = self
+            .images
+            .iter()
+            .filter(|image| image.measurement == report.measurement)
+            .filter(|image| {
+                image
+                    .host_data
+                    .as_ref()
+                    .is_none_or(|host_data| *host_data == report.host_data)
+            })
+            .collect();
+        if candidates.is_empty() {
+            bail!(
+                "Measurement {} with host data {} is not in the registry",
+                hex::encode(report.measurement),
+                hex::encode(report.host_data)
+            );
+        }
+
+        let mut problems = Vec::new();
+        for image in candidates {
+            match image.usable() {
+                Ok(()) => return Ok(image),
+                Err(e) => problems.push(format!("{}: {e}", image.name)),
+            }
+        }
+        bail!(
+            "Measurement matches no usable image ({})",
+            problems.join("; ")
+        )
+    }
+}
+
+impl Image {
+    fn from_entry(entry: ImageEntry) -> anyhow::Result<Self> {
+        let measurement = decode_hex(&entry.measurement, 48)
+            .with_context(|| format!("{}: invalid measurement", entry.name))?;
+        let host_data = entry
+            .host_data
+            .as_deref()
+            .map(|host_data| decode_hex(host_data, 32))
+            .transpose()
+            .with_context(|| format!("{}: invalid host_data", entry.name))?;
+        let date = |date: &Option<String>| date.as_deref().map(parse_date).transpose();
+        Ok(Self {
+            measurement,
+            host_data,
+            not_before: date(&entry.not_before)
+                .with_context(|| format!("{}: invalid not_before", entry.name))?,
+            not_after: date(&entry.not_after)
+                .with_context(|| format!("{}: invalid not_after", entry.name))?,
+            revoked: entry.revoked,
+            name: entry.name,
+            description: entry.description,
+        })
+    }
+
+    /// Check that the image is neither revoked nor outside its validity window.
+    fn usable(&self) -> anyhow::Result<()> {
+        ensure!(!self.revoked, "revoked");
+        let now = Asn1Time::days_from_now(0)?;
+        if let Some(not_before) = &self.not_before {
+            ensure!(
+                not_before.compare(&now)?.is_le(),
+                "not valid before {}",
+                &**not_before
+            );
+        }
+        if let Some(not_after) = &self.not_after {
+            ensure!(
+                not_after.compare(&now)?.is_ge(),
+                "expired at {}",
+                &**not_after
+            );
+        }
+        Ok(())
+    }
+}
+
+fn load_file(path: &Path) -> anyhow::Result<Vec<ImageEntry>> {
+    let file: RegistryFile = load_config(path, "registry")?;
+    Ok(file.image)
+}
+
+fn decode_hex(value: &str, len: usize) -> anyhow::Result<Vec<u8>> {
+    let bytes = hex::decode(value.trim()).context("not valid hex")?;
+    ensure!(
+        bytes.len() == len,
+        "expected {len} bytes, got {}",
+        bytes.len()
+    );
+    Ok(bytes)
+}
+
+/// Parse `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SSZ`, in UTC.
+fn parse_date(date: &str) -> anyhow::Result<Asn1Time> {
+    // `9` stands for any digit.
+    let matches = |pattern: &str| {
+        date.len() == pattern.len()
+            && date.bytes().zip(pattern.bytes()).all(|(c, p)| match p {
+                b'9' => c.is_ascii_digit(),
+                _ => c == p,
+            })
+    };
+    let digits: String = date.chars().filter(char::is_ascii_digit).collect();
+    let generalized = if matches("9999-99-99") {
+        format!("{digits}000000Z")
+    } else if matches("9999-99-99T99:99:99Z") {
+        format!("{digits}Z")
+    } else {
+        bail!("{date:?} is not YYYY-MM-DD or YYYY-MM-DDTHH:MM:SSZ")
+    };
+    Asn1Time::from_str(&generalized).with_context(|| format!("{date:?} is not a valid date"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn registry(contents: &str) -> anyhow::Result<Registry> {
+        let dir = TempDir::new("registry");
+        Registry::load(&dir.write("registry.toml", contents))
+    }
+
+    /// An image entry for the measurement of `report()`, plus `extra` fields.
+    fn image(name: &str, extra: &str) -> String {
+        format!(
+            "[[image]]\nname = \"{name}\"\nmeasurement = \"{}\"\n{extra}\n",
+            "aa".repeat(48)
+        )
+    }
+
+    fn report() -> AttestationReport {
+        let mut report = AttestationReport::default();
+        report.measurement = [0xaa; 48];
+        report.host_data = [0x11; 32];
+        report
+    }
+
+    fn find_error(contents: &str) -> String {
+        let registry = registry(contents).unwrap();
+        format!("{:#}", registry.find(&report()).err().unwrap())
+    }
+
+    #[test]
+    fn finds_usable_image() {
+        let registry = registry(&[
+            image("old", "revoked = true"),
+            image(
+                "current",
+                &format!(
+                    "host_data = \"{}\"\nnot_before = \"2000-01-01\"\nnot_after = \"9999-12-31T23:59:59Z\"",
+                    "11".repeat(32)
+                ),
+            ),
+        ]
+        .concat())
+        .unwrap();
+        assert_eq!(registry.find(&report()).unwrap().name, "current");
+    }
+
+    #[test]
+    fn revoked_image() {
+        assert_eq!(
+            find_error(&image("old", "revoked = true")),
+            "Measurement matches no usable image (old: revoked)"
+        );
+    }
+
+    #[test]
+    fn outside_validity_window() {
+        let err = find_error(&image("future", "not_before = \"9999-01-01\""));
+        assert!(
+            err.starts_with("Measurement matches no usable image (future: not valid before Jan  1 00:00:00 9999"),
+            "{err}"
+        );
+        let err = find_error(&image("expired", "not_after = \"2000-01-01T12:00:00Z\""));
+        assert!(
+            err.starts_with(
+                "Measurement matches no usable image (expired: expired at Jan  1 12:00:00 2000"
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn host_data_mismatch() {
+        let err = find_error(&image(
+            "other-host",
+            &format!("host_data = \"{}\"", "22".repeat(32)),
+        ));
+        assert_eq!(
+            err,
+            format!(
+                "Measurement {} with host data {} is not in the registry",
+                "aa".repeat(48),
+                "11".repeat(32)
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_measurement() {
+        let other = image("other", "").replace(&"aa".repeat(48), &"bb".repeat(48));
+        let err = find_error(&other);
+        assert!(err.ends_with("is not in the registry"), "{err}");
+        let err = find_error("");
+        assert!(err.ends_with("is not in the registry"), "{err}");
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let dir = TempDir::new("registry-dir");
+        dir.write("a.toml", image("app", ""));
+        dir.write(
+            "b.json",
+            format!(
+                r#"{{"image": [{{"name": "app", "measurement": "{}"}}]}}"#,
+                "bb".repeat(48)
+            ),
+        );
+        dir.write("notes.txt", "ignored");
+        let err = Registry::load(dir.path()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Registry image app is defined more than once"
+        );
+    }
+
+    #[test]
+    fn date_formats() {
+        assert!(parse_date("2024-01-02").is_ok());
+        assert!(parse_date("2024-01-02T03:04:05Z").is_ok());
+        for date in [
+            "2024/01/02",
+            "20240102",
+            "2024-0102",
+            "2024-01-02T03:04:05",
+            "2024-01-02 03:04:05Z",
+            "2024-01-02T030405Z",
+            "24-01-02",
+            "2024-1-2",
+            " 2024-01-02",
+            "",
+        ] {
+            let err = parse_date(date).err().unwrap();
+            assert!(
+                err.to_string().contains("is not YYYY-MM-DD"),
+                "{date}: {err}"
+            );
+        }
+        for date in ["2024-13-01", "2024-02-30", "2024-01-02T25:00:00Z"] {
+            assert!(parse_date(date).is_err(), "{date}");
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{bail, Context};
+use openssl::sha::sha512;
+use serde::{Deserialize, Serialize};
+use sev::firmware::guest::AttestationReport;
+use std::fmt;
+
+/// Size in bytes of the raw `AttestationReport` structure.
+pub const REPORT_SIZE: usize = 0x4A0;
+
+/// Size of an MSG_REPORT_RSP: a 32-byte status header followed by the report.
+const REPORT_RESPONSE_SIZE: usize = 0x20 + REPORT_SIZE;
+
+/// Report versions with a known layout: 2, 3 (CPUID) and 5 (mitigation vectors).
+pub const SUPPORTED_VERSIONS: [u32; 3] = [2, 3, 5];
+
+/// Length of the report prefix covered by the signature (bytes 0 to 0x29F).
+pub const SIGNED_LEN: usize = 0x2A0;
+
+/// ECDSA P-384 with SHA-384, the only signature algorithm defined for reports.
+pub const SIG_ALGO_ECDSA_P384_SHA384: u32 = 1;
+
+/// Size of the guest-supplied REPORT_DATA field.
+pub const REPORT_DATA_SIZE: usize = 64;
+
+/// Offset of the 64-bit guest policy.
+pub const POLICY_OFFSET: usize = 0x08;
+
+/// Offset of the 64-bit platform information field.
+pub const PLATFORM_INFO_OFFSET: usize = 0x40;
+
+/// Offset of the 32-bit key information field.
+pub const KEY_INFO_OFFSET: usize = 0x48;
+
+/// Offset of the CPUID family, model and stepping bytes (version 3+).
+pub const CPUID_OFFSET: usize = 0x188;
+
+/// Offsets of the 64-bit launch and current mitigation vectors (version 5+).
+pub const LAUNCH_MIT_VECTOR_OFFSET: usize = 0x1F8;
+pub const CURRENT_MIT_VECTOR_OFFSET: usize = 0x200;
+
+/// Parse a raw attestation report as returned by the firmware.
+pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<AttestationReport> {
+    match bytes.len() {
+        REPORT_SIZE => {}
+        0 => bail!("Attestation report is empty"),
+        REPORT_RESPONSE_SIZE => bail!(
+            "Input is {REPORT_RESPONSE_SIZE} bytes, the size of a firmware MSG_REPORT_RSP; \
+             strip its 32-byte header to get the {REPORT_SIZE}-byte report"
+        ),
+        len => bail!("Attestation report must be {REPORT_SIZE} bytes, got {len}"),
+    }
+
+    let version = u32::from_le_bytes(bytes[..4].try_into()?);
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        bail!("Unsupported attestation report version {version}, expected 2, 3 or 5");
+    }
+
+    bincode::deserialize(bytes).context("Failed to decode attestation report")
+}
+
+/// Serialize an attestation report back into its raw firmware layout.
+pub fn to_bytes(report: &AttestationReport) -> anyhow::Result<Vec<u8>> {
+    bincode::serialize(report).context("Failed to encode attestation report")
+}
+
+/// REPORT_DATA binding `input`: the input itself, zero-padded, if it fits,
+/// otherwise its SHA-512 digest.
+pub fn report_data(input: &[u8]) -> [u8; REPORT_DATA_SIZE] {
+    if input.len() > REPORT_DATA_SIZE {
+        log::info!(
+            "Report data is {} bytes, using its SHA-512 digest",
+            input.len()
+        );
+        return sha512(input);
+    }
+    let mut data = [0u8; REPORT_DATA_SIZE];
+    data[..input.len()].copy_from_slice(input);
+    data
+}
+
+/// CPUID family, model and stepping of the chip, for version 3+ reports.
+pub fn cpuid(report: &AttestationReport) -> anyhow::Result<Option<(u8, u8, u8)>> {
+    if report.version < 3 {
+        return Ok(None);
+    }
+    let bytes = to_bytes(report)?;
+    let cpuid = &bytes[CPUID_OFFSET..CPUID_OFFSET + 3];
+    Ok(Some((cpuid[0], cpuid[1], cpuid[2])))
+}
+
+/// Launch and current mitigation vectors, for version 5+ reports.
+pub fn mit_vectors(report: &AttestationReport) -> anyhow::Result<Option<(u64, u64)>> {
+    if report.version < 5 {
+        return Ok(None);
+    }
+    let bytes = to_bytes(report)?;
+    let u64_at = |offset: usize| -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(bytes[offset..offset + 8].try_into()?))
+    };
+    Ok(Some((
+        u64_at(LAUNCH_MIT_VECTOR_OFFSET)?,
+        u64_at(CURRENT_MIT_VECTOR_OFFSET)?,
+    )))
+}
+
+/// The key that signed a report, from KEY_INFO bits 4:2.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningKey {
+    /// Versioned Chip Endorsement Key, issued per chip by the ASK.
+    #[default]
+    Vcek,
+    /// Versioned Loaded Endorsement Key, issued per cloud provider by the ASVK.
+    Vlek,
+    /// The report is unsigned.
+    None,
+}
+
+impl SigningKey {
+    /// Encode as KEY_INFO bits 4:2.
+    pub fn to_key_info(self) -> u32 {
+        let select = match self {
+            SigningKey::Vcek => 0,
+            SigningKey::Vlek => 1,
+            SigningKey::None => 7,
+        };
+        select << 2
+    }
+
+    /// The name of the intermediate CA between this key and the ARK.
+    pub fn issuer_name(self) -> &'static str {
+        match self {
+            SigningKey::Vlek => "ASVK",
+            _ => "ASK",
+        }
+    }
+
+    /// The KDS service path prefix for this key's certificates.
+    pub fn kds_service(self) -> &'static str {
+        match self {
+            SigningKey::Vlek => "vlek",
+            _ => "vcek",
+        }
+    }
+}
+
+impl fmt::Display for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SigningKey::Vcek => "VCEK",
+            SigningKey::Vlek => "VLEK",
+            SigningKey::None => "none",
+        })
+    }
+}
+
+/// The raw KEY_INFO field, which the sev crate keeps private.
+pub fn key_info(report: &AttestationReport) -> anyhow::Result<u32> {
+    let bytes = to_bytes(report)?;
+    let raw = &bytes[KEY_INFO_OFFSET..KEY_INFO_OFFSET + 4];
+    Ok(u32::from_le_bytes(raw.try_into()?))
+}
+
+/// The raw guest POLICY the report was launched with.
+pub fn policy(report: &AttestationReport) -> anyhow::Result<u64> {
+    let bytes = to_bytes(report)?;
+    let raw = &bytes[POLICY_OFFSET..POLICY_OFFSET + 8];
+    Ok(u64::from_le_bytes(raw.try_into()?))
+}
+
+/// Which key signed `report`.
+pub fn signing_key(report: &AttestationReport) -> anyhow::Result<SigningKey> {
+    match (key_info(report)? >> 2) & 0x7 {
+        0 => Ok(SigningKey::Vcek),
+        1 => Ok(SigningKey::Vlek),
+        7 => Ok(SigningKey::None),
+        select => bail!("Reserved SIGNING_KEY value {select} in report KEY_INFO"),
+    }
+}
+
+/// True if the firmware zeroed the chip ID because MASK_CHIP_ID is set.
+pub fn chip_id_masked(report: &AttestationReport) -> bool {
+    report.chip_id.iter().all(|b| *b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A raw report of `version` with CPUID and mitigation vector bytes set,
+    /// whatever the version.
+    fn raw(version: u32) -> Vec<u8> {
+        let mut bytes = vec![0u8; REPORT_SIZE];
+        bytes[..4].copy_from_slice(&version.to_le_bytes());
+        bytes[POLICY_OFFSET..POLICY_OFFSET + 8].copy_from_slice(&0x30000u64.to_le_bytes());
+        bytes[CPUID_OFFSET..CPUID_OFFSET + 3].copy_from_slice(&[0x19, 0x11, 0x01]);
+        bytes[LAUNCH_MIT_VECTOR_OFFSET..LAUNCH_MIT_VECTOR_OFFSET + 8]
+            .copy_from_slice(&3u64.to_le_bytes());
+        bytes[CURRENT_MIT_VECTOR_OFFSET..CURRENT_MIT_VECTOR_OFFSET + 8]
+            .copy_from_slice(&7u64.to_le_bytes());
+        bytes
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        from_bytes(bytes).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(error(&[]), "Attestation report is empty");
+        assert_eq!(
+            error(&[0; REPORT_SIZE - 1]),
+            "Attestation report must be 1184 bytes, got 1183"
+        );
+        assert_eq!(
+            error(&[0; REPORT_SIZE + 1]),
+            "Attestation report must be 1184 bytes, got 1185"
+        );
+        assert!(error(&[0; REPORT_RESPONSE_SIZE])
+            .starts_with("Input is 1216 bytes, the size of a firmware MSG_REPORT_RSP"));
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        for version in [0, 1, 4, 6, u32::MAX] {
+            assert_eq!(
+                error(&raw(version)),
+                format!("Unsupported attestation report version {version}, expected 2, 3 or 5")
+            );
+        }
+    }
+
+    #[test]
+    fn version_2() {
+        let report = from_bytes(&raw(2)).unwrap();
+        assert_eq!(report.version, 2);
+        assert_eq!(policy(&report).unwrap(), 0x30000);
+        assert_eq!(cpuid(&report).unwrap(), None);
+        assert_eq!(mit_vectors(&report).unwrap(), None);
+    }
+
+    #[test]
+    fn version_3() {
+        let report = from_bytes(&raw(3)).unwrap();
+        assert_eq!(report.version, 3);
+        assert_eq!(cpuid(&report).unwrap(), Some((0x19, 0x11, 0x01)));
+        assert_eq!(mit_vectors(&report).unwrap(), None);
+    }
+
+    #[test]
+    fn version_5() {
+        let report = from_bytes(&raw(5)).unwrap();
+        assert_eq!(report.version, 5);
+        assert_eq!(cpuid(&report).unwrap(), Some((0x19, 0x11, 0x01)));
+        assert_eq!(mit_vectors(&report).unwrap(), Some((3, 7)));
+        assert_eq!(to_bytes(&report).unwrap(), raw(5));
+    }
+
+    #[test]
+    fn signing_keys() {
+        for key in [SigningKey::Vcek, SigningKey::Vlek, SigningKey::None] {
+            let mut bytes = raw(5);
+            bytes[KEY_INFO_OFFSET..KEY_INFO_OFFSET + 4]
+                .copy_from_slice(&key.to_key_info().to_le_bytes());
+            assert_eq!(signing_key(&from_bytes(&bytes).unwrap()).unwrap(), key);
+        }
+        let mut bytes = raw(5);
+        bytes[KEY_INFO_OFFSET] = 2 << 2;
+        let err = signing_key(&from_bytes(&bytes).unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Reserved SIGNING_KEY value 2 in report KEY_INFO"
+        );
+    }
+}
@@ -0,0 +1,769 @@
+//! Tenant-defined acceptance rules over report claims.
+//!
+//! A rules file is TOML or JSON with named lists and named boolean rules:
+//!
+//! ```toml
+//! [lists]
+//! allowed = ["5f2a...e1", "09c4...7d"]
+//!
+//! [[rule]]
+//! name = "production-launch"
+//! expr = 'debug == false && vmpl == 0 && measurement in allowed'
+//!
+//! [[rule]]
+//! name = "our-family"
+//! expr = 'family_id == "00112233445566778899aabbccddeeff" && guest_svn >= 2'
+//! ```
+//!
+//! Claims are the fields of `report --format json`, nested fields joined with
+//! dots (`policy.debug_allowed`, `reported_tcb.snp`, `key_info.signing_key`),
+//! plus the shorthands `debug`, `smt`, `migrate_ma` and `signing_key`, and
+//! `matched_image` when a measurement registry is used. Expressions support
+//! `&&`, `||`, `!`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `in`, parentheses,
+//! `[..]` lists, integers (decimal or 0x hex), "strings", `true`, `false`
+//! and `null`. Strings compare case-insensitively, so hex may use either case.
+
+use anyhow::{bail, ensure, Context};
+use serde::{Deserialize, Serialize};
+use sev::firmware::guest::AttestationReport;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    path::Path,
+};
+
+use crate::{config::load_config, decoded::DecodedReport, product::Product};
+
+/// Claim names that stand for a longer one.
+const SHORTHANDS: &[(&str, &str)] = &[
+    ("debug", "policy.debug_allowed"),
+    ("smt", "policy.smt_allowed"),
+    ("migrate_ma", "policy.migrate_ma_allowed"),
+    ("signing_key", "key_info.signing_key"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i128),
+    Str(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Int(_) => "integer",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+        }
+    }
+
+    fn from_json(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            serde_json::Value::Number(n) => match n.as_u64() {
+                Some(n) => Value::Int(n.into()),
+                None => Value::Int(n.as_i64().unwrap_or_default().into()),
+            },
+            serde_json::Value::String(s) => Value::Str(s.clone()),
+            serde_json::Value::Array(items) => {
+                Value::List(items.iter().map(Value::from_json).collect())
+            }
+            serde_json::Value::Object(_) => Value::Null,
+        }
+    }
+
+    fn equals(&self, other: &Value) -> anyhow::Result<bool> {
+        Ok(match (self, other) {
+            (Value::Str(a), Value::Str(b)) => a.eq_ignore_ascii_case(b),
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Null, Value::Null) => true,
+            (Value::Null, _) | (_, Value::Null) => false,
+            (Value::List(a), Value::List(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .map(|(a, b)| a.equals(b))
+                        .collect::<anyhow::Result<Vec<_>>>()?
+                        .into_iter()
+                        .all(|eq| eq)
+            }
+            (a, b) => bail!("cannot compare {} with {}", a.type_name(), b.type_name()),
+        })
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Str(s) => write!(f, "{s:?}"),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+/// The claims rules are evaluated against.
+pub struct Claims(BTreeMap<String, Value>);
+
+impl Claims {
+    pub fn new(report: &DecodedReport, matched_image: Option<&str>) -> anyhow::Result<Self> {
+        let mut claims = BTreeMap::new();
+        flatten("", &serde_json::to_value(report)?, &mut claims);
+        for (short, long) in SHORTHANDS {
+            if let Some(value) = claims.get(*long).cloned() {
+                claims.insert(short.to_string(), value);
+            }
+        }
+        let image = matched_image.map_or(Value::Null, |name| Value::Str(name.to_string()));
+        claims.insert("matched_image".to_string(), image);
+        Ok(Self(claims))
+    }
+
+    /// Every name a claim can have, and every dotted prefix of one, over all
+    /// report versions and products.
+    fn names() -> anyhow::Result<BTreeSet<String>> {
+        let mut report = AttestationReport::default();
+        report.version = 5;
+        let claims = Self::new(&DecodedReport::new(&report, Product::Turin)?, None)?;
+        Ok(claims
+            .0
+            .keys()
+            .flat_map(|name| {
+                name.match_indices('.')
+                    .map(|(end, _)| name[..end].to_string())
+                    .chain([name.clone()])
+            })
+            .collect())
+    }
+}
+
+fn flatten(prefix: &str, value: &serde_json::Value, claims: &mut BTreeMap<String, Value>) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (name, value) in fields {
+                let name = match prefix {
+                    "" => name.clone(),
+                    prefix => format!("{prefix}.{name}"),
+                };
+                flatten(&name, value, claims);
+            }
+        }
+        value => {
+            claims.insert(prefix.to_string(), Value::from_json(value));
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListItem {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    lists: HashMap<String, Vec<ListItem>>,
+    #[serde(default)]
+    rule: Vec<RuleEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    name: String,
+    expr: String,
+}
+
+struct Rule {
+    name: String,
+    source: String,
+    expr: Expr,
+}
+
+pub struct Rules {
+    lists: HashMap<String, Value>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleStatus {
+    Passed,
+    Failed,
+    /// The rule could not be evaluated, e.g. it names an unknown claim.
+    Error,
+}
+
+/// The outcome of one named rule.
+#[derive(Debug, Serialize)]
+pub struct RuleResult {
+    pub name: String,
+    pub status: RuleStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Rules {
+    /// Load and parse a rules file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file: RulesFile = load_config(path, "rules")?;
+
+        let claims = Claims::names()?;
+        if let Some(name) = file.lists.keys().find(|name| claims.contains(*name)) {
+            bail!(
+                "List {name} in {} has the name of a claim, rename it",
+                path.display()
+            );
+        }
+        let lists = file
+            .lists
+            .into_iter()
+            .map(|(name, items)| {
+                let items = items
+                    .into_iter()
+                    .map(|item| match item {
+                        ListItem::Bool(b) => Value::Bool(b),
+                        ListItem::Int(n) => Value::Int(n.into()),
+                        ListItem::Str(s) => Value::Str(s),
+                    })
+                    .collect();
+                (name, Value::List(items))
+            })
+            .collect();
+
+        let mut rules: Vec<Rule> = Vec::new();
+        for entry in file.rule {
+            ensure!(
+                !rules.iter().any(|rule| rule.name == entry.name),
+                "Rule {} is defined more than once in {}",
+                entry.name,
+                path.display()
+            );
+            let expr = parse(&entry.expr)
+                .with_context(|| format!("Invalid rule {} in {}", entry.name, path.display()))?;
+            rules.push(Rule {
+                name: entry.name,
+                source: entry.expr,
+                expr,
+            });
+        }
+        ensure!(!rules.is_empty(), "No rules in {}", path.display());
+        Ok(Self { lists, rules })
+    }
+
+    /// Evaluate every rule against `claims`.
+    pub fn evaluate(&self, claims: &Claims) -> Vec<RuleResult> {
+        let scope = Scope {
+            claims,
+            lists: &self.lists,
+        };
+        self.rules
+            .iter()
+            .map(|rule| {
+                let (status, message) = match scope.eval(&rule.expr) {
+                    Ok(Value::Bool(true)) => (RuleStatus::Passed, None),
+                    Ok(Value::Bool(false)) => (
+                        RuleStatus::Failed,
+                        Some(format!("{} is false", rule.source)),
+                    ),
+                    Ok(value) => (
+                        RuleStatus::Error,
+                        Some(format!("evaluates to {}, not a boolean", value.type_name())),
+                    ),
+                    Err(e) => (RuleStatus::Error, Some(format!("{e:#}"))),
+                };
+                RuleResult {
+                    name: rule.name.clone(),
+                    status,
+                    message,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Ident(String),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+struct Scope<'a> {
+    claims: &'a Claims,
+    lists: &'a HashMap<String, Value>,
+}
+
+impl Scope<'_> {
+    fn eval(&self, expr: &Expr) -> anyhow::Result<Value> {
+        Ok(match expr {
+            Expr::Literal(value) => value.clone(),
+            Expr::Ident(name) => self
+                .claims
+                .0
+                .get(name)
+                .or_else(|| self.lists.get(name))
+                .with_context(|| format!("unknown claim or list {name}"))?
+                .clone(),
+            Expr::List(items) => Value::List(
+                items
+                    .iter()
+                    .map(|item| self.eval(item))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            Expr::Not(inner) => Value::Bool(!self.boolean(inner)?),
+            Expr::Binary(Op::And, lhs, rhs) => {
+                Value::Bool(self.boolean(lhs)? && self.boolean(rhs)?)
+            }
+            Expr::Binary(Op::Or, lhs, rhs) => Value::Bool(self.boolean(lhs)? || self.boolean(rhs)?),
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (self.eval(lhs)?, self.eval(rhs)?);
+                Value::Bool(match (op, &lhs, &rhs) {
+                    (Op::Eq, _, _) => lhs.equals(&rhs)?,
+                    (Op::Ne, _, _) => !lhs.equals(&rhs)?,
+                    (Op::In, _, Value::List(items)) => {
+                        let mut found = false;
+                        for item in items {
+                            found |= lhs.equals(item)?;
+                        }
+                        found
+                    }
+                    (Op::In, _, _) => {
+                        bail!("right side of in is a {}, not a list", rhs.type_name())
+                    }
+                    (op, Value::Int(a), Value::Int(b)) => match op {
+                        Op::Lt => a < b,
+                        Op::Le => a <= b,
+                        Op::Gt => a > b,
+                        _ => a >= b,
+                    },
+                    _ => bail!("cannot order {} and {}", lhs.type_name(), rhs.type_name()),
+                })
+            }
+        })
+    }
+
+    fn boolean(&self, expr: &Expr) -> anyhow::Result<bool> {
+        match self.eval(expr)? {
+            Value::Bool(b) => Ok(b),
+            value => bail!("expected a boolean, got {}", value.type_name()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i128),
+    Str(String),
+    Op(Op),
+    Not,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+/// Split an expression into tokens, each with its byte offset.
+fn tokenize(source: &str) -> anyhow::Result<Vec<(usize, Token)>> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i] as char;
+        let two = source.get(i..i + 2).unwrap_or_default();
+        let token = match c {
+            _ if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ',' => Token::Comma,
+            _ if two == "&&" => Token::Op(Op::And),
+            _ if two == "||" => Token::Op(Op::Or),
+            _ if two == "==" => Token::Op(Op::Eq),
+            _ if two == "!=" => Token::Op(Op::Ne),
+            _ if two == "<=" => Token::Op(Op::Le),
+            _ if two == ">=" => Token::Op(Op::Ge),
+            '<' => Token::Op(Op::Lt),
+            '>' => Token::Op(Op::Gt),
+            '!' => Token::Not,
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match source[i..].chars().next() {
+                        None => bail!("unterminated string starting at {start}"),
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = source[i + 1..].chars().next();
+                            match escaped {
+                                Some(c @ ('"' | '\\')) => value.push(c),
+                                _ => bail!("invalid escape at {i}"),
+                            }
+                            i += 2;
+                        }
+                        Some(c) => {
+                            value.push(c);
+                            i += c.len_utf8();
+                        }
+                    }
+                }
+                i += 1;
+                tokens.push((start, Token::Str(value)));
+                continue;
+            }
+            _ if c.is_ascii_digit() => {
+                let end = source[i..]
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .map_or(source.len(), |len| i + len);
+                let literal = &source[i..end];
+                let value = match literal.strip_prefix("0x") {
+                    Some(hex) => i128::from_str_radix(hex, 16),
+                    None => literal.parse(),
+                }
+                .with_context(|| format!("invalid number {literal} at {start}"))?;
+                i = end;
+                tokens.push((start, Token::Int(value)));
+                continue;
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let end = source[i..]
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                    .map_or(source.len(), |len| i + len);
+                let word = &source[i..end];
+                i = end;
+                let token = match word {
+                    "in" => Token::Op(Op::In),
+                    word => Token::Ident(word.to_string()),
+                };
+                tokens.push((start, token));
+                continue;
+            }
+            _ => bail!("unexpected {c:?} at {start}"),
+        };
+        i += match token {
+            Token::Op(Op::Lt | Op::Gt) | Token::Not => 1,
+            Token::Op(_) => 2,
+            _ => 1,
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+fn parse(source: &str) -> anyhow::Result<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        len: source.len(),
+    };
+    let expr = parser.or()?;
+    if let Some((offset, token)) = parser.tokens.get(parser.pos) {
+        bail!("unexpected {token:?} at {offset}");
+    }
+    Ok(expr)
+}
+
+/// Recursive descent, loosest binding first: `||`, `&&`, `!`, comparisons.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> anyhow::Result<Token> {
+        let (_, token) = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .with_context(|| format!("unexpected end of expression at {}", self.len))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> anyhow::Result<()> {
+        let offset = self
+            .tokens
+            .get(self.pos)
+            .map_or(self.len, |(offset, _)| *offset);
+        let token = self.next()?;
+        ensure!(
+            token == expected,
+            "expected {expected:?} at {offset}, found {token:?}"
+        );
+        Ok(())
+    }
+
+    fn or(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Op(Op::Or)) {
+            self.pos += 1;
+            expr = Expr::Binary(Op::Or, Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::Op(Op::And)) {
+            self.pos += 1;
+            expr = Expr::Binary(Op::And, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> anyhow::Result<Expr> {
+        let lhs = self.primary()?;
+        match self.peek() {
+            Some(Token::Op(op)) if !matches!(op, Op::And | Op::Or) => {
+                let op = *op;
+                self.pos += 1;
+                Ok(Expr::Binary(op, Box::new(lhs), Box::new(self.primary()?)))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Expr> {
+        let offset = self
+            .tokens
+            .get(self.pos)
+            .map_or(self.len, |(offset, _)| *offset);
+        Ok(match self.next()? {
+            Token::Int(n) => Expr::Literal(Value::Int(n)),
+            Token::Str(s) => Expr::Literal(Value::Str(s)),
+            Token::Ident(word) => match word.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "null" => Expr::Literal(Value::Null),
+                _ => Expr::Ident(word),
+            },
+            Token::LParen => {
+                let expr = self.or()?;
+                self.expect(Token::RParen)?;
+                expr
+            }
+            Token::LBracket => {
+                let mut items = Vec::new();
+                if self.peek() != Some(&Token::RBracket) {
+                    items.push(self.or()?);
+                    while self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                        items.push(self.or()?);
+                    }
+                }
+                self.expect(Token::RBracket)?;
+                Expr::List(items)
+            }
+            token => bail!("unexpected {token:?} at {offset}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn claims() -> Claims {
+        Claims(BTreeMap::from([
+            ("debug".to_string(), Value::Bool(false)),
+            ("vmpl".to_string(), Value::Int(0)),
+            ("guest_svn".to_string(), Value::Int(3)),
+            ("measurement".to_string(), Value::Str("AB01".to_string())),
+            ("matched_image".to_string(), Value::Null),
+        ]))
+    }
+
+    fn eval_with(expr: &str, lists: &HashMap<String, Value>) -> anyhow::Result<Value> {
+        let claims = claims();
+        let scope = Scope {
+            claims: &claims,
+            lists,
+        };
+        scope.eval(&parse(expr)?)
+    }
+
+    fn eval(expr: &str) -> anyhow::Result<Value> {
+        eval_with(expr, &HashMap::new())
+    }
+
+    fn holds(expr: &str) -> bool {
+        match eval(expr).unwrap() {
+            Value::Bool(b) => b,
+            value => panic!("{expr} evaluates to {value}"),
+        }
+    }
+
+    fn error(expr: &str) -> String {
+        format!("{:#}", eval(expr).unwrap_err())
+    }
+
+    fn load(name: &str, contents: &str) -> anyhow::Result<Rules> {
+        let dir = TempDir::new("rules");
+        Rules::load(&dir.write(name, contents))
+    }
+
+    #[test]
+    fn precedence() {
+        assert!(holds("true || false && false"));
+        assert!(!holds("(true || false) && false"));
+        assert!(holds("!false && true"));
+        assert!(!holds("!(false || true)"));
+        assert!(holds("!debug && vmpl == 0"));
+        assert!(holds("false && false || true"));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(holds("guest_svn >= 3 && guest_svn < 0x4"));
+        assert!(holds("guest_svn != 2"));
+        assert!(holds("measurement == \"ab01\""));
+        assert!(holds("[1, \"a\"] == [1, \"A\"]"));
+    }
+
+    #[test]
+    fn in_literal_list() {
+        assert!(holds("vmpl in [0, 1]"));
+        assert!(!holds("guest_svn in [0, 1]"));
+        assert!(holds("measurement in [\"cd02\", \"ab01\"]"));
+        assert!(!holds("vmpl in []"));
+    }
+
+    #[test]
+    fn in_file_list() {
+        let rules = load(
+            "in.toml",
+            r#"
+            [lists]
+            approved = ["cd02", "ab01"]
+
+            [[rule]]
+            name = "approved"
+            expr = "measurement in approved"
+
+            [[rule]]
+            name = "not-approved"
+            expr = "!(measurement in approved)"
+            "#,
+        )
+        .unwrap();
+        let results = rules.evaluate(&claims());
+        assert_eq!(results[0].status, RuleStatus::Passed);
+        assert_eq!(results[1].status, RuleStatus::Failed);
+        assert_eq!(
+            results[1].message.as_deref(),
+            Some("!(measurement in approved) is false")
+        );
+    }
+
+    #[test]
+    fn list_named_like_claim() {
+        for name in ["measurement", "vmpl", "cpuid", "reported_tcb"] {
+            let err = load(
+                "shadow.json",
+                &format!(
+                    r#"{{"lists": {{"{name}": [1]}}, "rule": [{{"name": "r", "expr": "true"}}]}}"#
+                ),
+            )
+            .err()
+            .unwrap();
+            let err = format!("{err:#}");
+            assert!(err.contains("has the name of a claim"), "{err}");
+        }
+    }
+
+    #[test]
+    fn null_comparisons() {
+        assert!(holds("matched_image == null"));
+        assert!(!holds("matched_image != null"));
+        assert!(!holds("measurement == null"));
+        assert!(holds("measurement != null"));
+        assert!(!holds("matched_image == \"image\""));
+        assert!(holds("matched_image in [null]"));
+    }
+
+    #[test]
+    fn type_mismatch() {
+        assert_eq!(error("vmpl == \"0\""), "cannot compare integer with string");
+        assert_eq!(error("measurement < 1"), "cannot order string and integer");
+        assert_eq!(error("vmpl && true"), "expected a boolean, got integer");
+        assert_eq!(
+            error("vmpl in 0"),
+            "right side of in is a integer, not a list"
+        );
+    }
+
+    #[test]
+    fn unknown_identifier() {
+        assert_eq!(error("chip == 1"), "unknown claim or list chip");
+        let mut lists = HashMap::new();
+        lists.insert("chip".to_string(), Value::List(vec![Value::Int(1)]));
+        assert_eq!(eval_with("1 in chip", &lists).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |expr: &str| format!("{:#}", parse(expr).unwrap_err());
+        assert_eq!(err("vmpl == 0 true"), "unexpected Ident(\"true\") at 10");
+        assert_eq!(err("(vmpl == 0))"), "unexpected RParen at 11");
+        assert_eq!(err("vmpl =="), "unexpected end of expression at 7");
+        assert_eq!(err("(vmpl"), "unexpected end of expression at 5");
+        assert_eq!(err("\"open"), "unterminated string starting at 0");
+        assert_eq!(err("vmpl = 0"), "unexpected '=' at 5");
+        assert_eq!(
+            err("0xzz"),
+            "invalid number 0xzz at 0: invalid digit found in string"
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use anyhow::{bail, Context};
+use openssl::{
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    nid::Nid,
+    pkey::Private,
+    sha::{sha384, sha512, Sha256},
+};
+use serde::Deserialize;
+use sev::firmware::host::CertType;
+use sev::{
+    certs::snp::ecdsa::Signature,
+    firmware::{
+        guest::{AttestationReport, DerivedKey},
+        host::CertTableEntry,
+    },
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    certs,
+    firmware::ReportProvider,
+    product::Product,
+    report::{self, SigningKey},
+    tcb::Tcb,
+};
+
+/// TCB component SVNs reported by the simulated platform.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimTcb {
+    /// Only encoded for Turin.
+    pub fmc: Option<u8>,
+    pub bootloader: u8,
+    pub tee: u8,
+    pub snp: u8,
+    pub microcode: u8,
+}
+
+impl From<SimTcb> for Tcb {
+    fn from(tcb: SimTcb) -> Self {
+        Tcb {
+            fmc: tcb.fmc,
+            bootloader: tcb.bootloader,
+            tee: tcb.tee,
+            snp: tcb.snp,
+            microcode: tcb.microcode,
+        }
+    }
+}
+
+/// Fields of the synthetic attestation report, loaded from JSON.
+///
+/// Byte arrays are hex strings; any field left out keeps its default.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    /// Selects the TCB layout and the CPUID fields of version 3+ reports.
+    pub product: Product,
+    pub version: u32,
+    pub guest_svn: u32,
+    pub policy: u64,
+    #[serde(with = "hex")]
+    pub family_id: [u8; 16],
+    #[serde(with = "hex")]
+    pub image_id: [u8; 16],
+    pub vmpl: u32,
+    pub platform_info: u64,
+    pub tcb: SimTcb,
+    #[serde(with = "hex")]
+    pub measurement: [u8; 48],
+    #[serde(with = "hex")]
+    pub host_data: [u8; 32],
+    #[serde(with = "hex")]
+    pub report_data: [u8; 64],
+    #[serde(with = "hex")]
+    pub chip_id: [u8; 64],
+    /// Key the report claims to be signed by; --sim-key must match it.
+    pub signing_key: SigningKey,
+    /// gen-test-pki directory whose certificates fill the extended report
+    /// certificate table.
+    pub cert_dir: Option<PathBuf>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            product: Product::Genoa,
+            version: 3,
+            guest_svn: 0,
+            // ABI 0.0, SMT allowed and the reserved bit 17 set.
+            policy: 0x30000,
+            family_id: [0; 16],
+            image_id: [0; 16],
+            vmpl: 0,
+            platform_info: 0,
+            tcb: SimTcb::default(),
+            measurement: [0; 48],
+            host_data: [0; 32],
+            report_data: [0; 64],
+            // All zeros would read as MASK_CHIP_ID, so pick a fixed chip.
+            chip_id: sha512(b"sev-test simulated chip"),
+            signing_key: SigningKey::Vcek,
+            cert_dir: None,
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read(path)
+            .with_context(|| format!("Failed to read simulator config {}", path.display()))?;
+        serde_json::from_slice(&json)
+            .with_context(|| format!("Invalid simulator config {}", path.display()))
+    }
+}
+
+/// Load a P-384 VCEK private key from PEM, or generate a throwaway one.
+pub fn load_vcek_key(path: Option<&Path>) -> anyhow::Result<EcKey<Private>> {
+    let Some(path) = path else {
+        log::warn!("No --sim-key given, signing reports with an ephemeral VCEK key");
+        let group = EcGroup::from_curve_name(Nid::SECP384R1)?;
+        return Ok(EcKey::generate(&group)?);
+    };
+
+    let pem =
+        fs::read(path).with_context(|| format!("Failed to read VCEK key {}", path.display()))?;
+    let key = EcKey::private_key_from_pem(&pem)
+        .with_context(|| format!("Invalid VCEK key {}", path.display()))?;
+
+    if key.group().curve_name() != Some(Nid::SECP384R1) {
+        bail!("VCEK key {} is not a P-384 key", path.display());
+    }
+
+    Ok(key)
+}
+
+/// In-process stand-in for the AMD Secure Processor that signs synthetic
+/// attestation reports with a test VCEK.
+pub struct SimulatedFirmware {
+    config: SimConfig,
+    vcek: EcKey<Private>,
+}
+
+impl SimulatedFirmware {
+    pub fn new(config: SimConfig, vcek: EcKey<Private>) -> Self {
+        Self { config, vcek }
+    }
+
+    fn build_report(
+        &self,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<AttestationReport> {
+        let config = &self.config;
+        let tcb = Tcb::from(config.tcb).to_version(config.product)?;
+
+        let mut report = AttestationReport::default();
+        report.version = config.version;
+        report.guest_svn = config.guest_svn;
+        report.family_id = config.family_id;
+        report.image_id = config.image_id;
+        report.vmpl = vmpl.unwrap_or(config.vmpl);
+        report.sig_algo = report::SIG_ALGO_ECDSA_P384_SHA384;
+        report.current_tcb = tcb;
+        report.reported_tcb = tcb;
+        report.committed_tcb = tcb;
+        report.launch_tcb = tcb;
+        report.report_data = data.unwrap_or(config.report_data);
+        report.measurement = config.measurement;
+        report.host_data = config.host_data;
+        report.chip_id = config.chip_id;
+
+        let mut id = Sha256::new();
+        id.update(&config.chip_id);
+        id.update(&config.measurement);
+        report.report_id = id.finish();
+
+        // The policy and platform info bitfields have no setters and the
+        // CPUID bytes are reserved in the sev crate, so they are patched
+        // into the raw layout before signing.
+        let mut bytes = report::to_bytes(&report)?;
+        bytes[report::POLICY_OFFSET..report::POLICY_OFFSET + 8]
+            .copy_from_slice(&config.policy.to_le_bytes());
+        bytes[report::PLATFORM_INFO_OFFSET..report::PLATFORM_INFO_OFFSET + 8]
+            .copy_from_slice(&config.platform_info.to_le_bytes());
+        bytes[report::KEY_INFO_OFFSET..report::KEY_INFO_OFFSET + 4]
+            .copy_from_slice(&config.signing_key.to_key_info().to_le_bytes());
+        if config.version >= 3 {
+            let (family, model) = config.product.cpuid();
+            bytes[report::CPUID_OFFSET..report::CPUID_OFFSET + 3]
+                .copy_from_slice(&[family, model, 1]);
+        }
+
+        let digest = sha384(&bytes[..report::SIGNED_LEN]);
+        let sig = EcdsaSig::sign(&digest, &self.vcek).context("Failed to sign report")?;
+
+        let mut report = report::from_bytes(&bytes)?;
+        report.signature = Signature::from(sig);
+
+        Ok(report)
+    }
+
+    /// ARK, ASK or ASVK, VCEK or VLEK, and the CRL if there is one, from
+    /// the configured directory, as a host would provide them.
+    fn cert_table(&self) -> anyhow::Result<Vec<CertTableEntry>> {
+        let config = &self.config;
+        let Some(dir) = &config.cert_dir else {
+            return Ok(Vec::new());
+        };
+        let (ask, leaf, crl) = match config.signing_key {
+            SigningKey::Vlek => (
+                "asvk.pem".to_string(),
+                "vlek.der".to_string(),
+                "vlek_crl.der",
+            ),
+            _ => (
+                "ask.pem".to_string(),
+                format!("vcek-{}.der", hex::encode(&config.chip_id[..8])),
+                "crl.der",
+            ),
+        };
+
+        let mut entries = Vec::new();
+        for (cert_type, name) in [
+            (CertType::ARK, "ark.pem".to_string()),
+            (CertType::ASK, ask),
+            (certs::cert_type(config.signing_key), leaf),
+        ] {
+            let cert = certs::load_cert(&dir.join(name))?;
+            entries.push(CertTableEntry::new(cert_type, cert.to_der()?));
+        }
+        if let Ok(crl) = fs::read(dir.join(crl)) {
+            entries.push(CertTableEntry::new(certs::crl_type(), crl));
+        }
+        Ok(entries)
+    }
+}
+
+impl ReportProvider for SimulatedFirmware {
+    fn get_report(
+        &mut self,
+        _message_version: Option<u8>,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<AttestationReport> {
+        self.build_report(data, vmpl)
+    }
+
+    fn get_ext_report(
+        &mut self,
+        _message_version: Option<u8>,
+        data: Option<[u8; 64]>,
+        vmpl: Option<u32>,
+    ) -> anyhow::Result<(AttestationReport, Vec<CertTableEntry>)> {
+        Ok((self.build_report(data, vmpl)?, self.cert_table()?))
+    }
+
+    fn get_derived_key(
+        &mut self,
+        _message_version: Option<u8>,
+        request: DerivedKey,
+    ) -> anyhow::Result<[u8; 32]> {
+        // Not the firmware's KDF, only a stable function of the same inputs.
+        let config = &self.config;
+        let select = request.guest_field_select;
+
+        let mut hasher = Sha256::new();
+        hasher.update(&self.vcek.private_key().to_vec());
+        hasher.update(&request.get_root_key_select().to_le_bytes());
+        hasher.update(&request.vmpl.to_le_bytes());
+        if select.get_guest_policy() == 1 {
+            hasher.update(&config.policy.to_le_bytes());
+        }
+        if select.get_image_id() == 1 {
+            hasher.update(&config.image_id);
+        }
+        if select.get_family_id() == 1 {
+            hasher.update(&config.family_id);
+        }
+        if select.get_measurement() == 1 {
+            hasher.update(&config.measurement);
+        }
+        if select.get_svn() == 1 {
+            hasher.update(&request.guest_svn.to_le_bytes());
+        }
+        if select.get_tcb_version() == 1 {
+            hasher.update(&request.tcb_version.to_le_bytes());
+        }
+
+        Ok(hasher.finish())
+    }
+}
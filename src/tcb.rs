@@ -0,0 +1,269 @@
+use anyhow::Context;
+use serde::Serialize;
+use sev::firmware::host::TcbVersion;
+use std::fmt;
+
+use crate::product::Product;
+
+/// A TCB version decoded with its product's layout.
+///
+/// Milan and Genoa store `[bl, tee, 0, 0, 0, 0, snp, ucode]`; Turin adds the
+/// FMC SVN as `[fmc, bl, tee, snp, 0, 0, 0, ucode]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Tcb {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fmc: Option<u8>,
+    pub bootloader: u8,
+    pub tee: u8,
+    pub snp: u8,
+    pub microcode: u8,
+}
+
+impl Tcb {
+    pub fn from_raw(product: Product, raw: [u8; 8]) -> Self {
+        match product {
+            Product::Turin => Self {
+                fmc: Some(raw[0]),
+                bootloader: raw[1],
+                tee: raw[2],
+                snp: raw[3],
+                microcode: raw[7],
+            },
+            Product::Milan | Product::Genoa => Self {
+                fmc: None,
+                bootloader: raw[0],
+                tee: raw[1],
+                snp: raw[6],
+                microcode: raw[7],
+            },
+        }
+    }
+
+    pub fn to_raw(self, product: Product) -> [u8; 8] {
+        match product {
+            Product::Turin => [
+                self.fmc.unwrap_or(0),
+                self.bootloader,
+                self.tee,
+                self.snp,
+                0,
+                0,
+                0,
+                self.microcode,
+            ],
+            Product::Milan | Product::Genoa => [
+                self.bootloader,
+                self.tee,
+                0,
+                0,
+                0,
+                0,
+                self.snp,
+                self.microcode,
+            ],
+        }
+    }
+
+    /// Reinterpret a `TcbVersion`, which the `sev` crate always decodes
+    /// with the Milan/Genoa layout.
+    pub fn from_version(product: Product, version: &TcbVersion) -> anyhow::Result<Self> {
+        let raw: [u8; 8] = bincode::serialize(version)?
+            .try_into()
+            .ok()
+            .context("TCB version is not 8 bytes")?;
+        Ok(Self::from_raw(product, raw))
+    }
+
+    pub fn to_version(self, product: Product) -> anyhow::Result<TcbVersion> {
+        Ok(bincode::deserialize(&self.to_raw(product))?)
+    }
+
+    /// Named SVNs of every component present in this TCB.
+    pub fn components(&self) -> Vec<(&'static str, u8)> {
+        let mut components = Vec::new();
+        if let Some(fmc) = self.fmc {
+            components.push(("fmc", fmc));
+        }
+        components.extend([
+            ("bootloader", self.bootloader),
+            ("tee", self.tee),
+            ("snp", self.snp),
+            ("microcode", self.microcode),
+        ]);
+        components
+    }
+
+    /// True if no component is newer than in `other` and at least one is older.
+    pub fn is_older_than(&self, other: &Tcb) -> bool {
+        self != other
+            && self
+                .components()
+                .iter()
+                .zip(other.components())
+                .all(|((_, ours), (_, theirs))| *ours <= theirs)
+    }
+
+    /// The SPL query parameters KDS expects for a VCEK request.
+    pub fn kds_query(&self) -> String {
+        let spls = format!(
+            "blSPL={:02}&teeSPL={:02}&snpSPL={:02}&ucodeSPL={:02}",
+            self.bootloader, self.tee, self.snp, self.microcode
+        );
+        match self.fmc {
+            Some(fmc) => format!("fmcSPL={fmc:02}&{spls}"),
+            None => spls,
+        }
+    }
+
+    /// Parse a KDS VCEK query string; `fmcSPL` is required for Turin only.
+    pub fn from_kds_query(product: Product, query: &str) -> anyhow::Result<Self> {
+        let param = |name: &str| -> anyhow::Result<Option<u8>> {
+            let Some(value) = query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find_map(|(key, value)| (key == name).then_some(value))
+            else {
+                return Ok(None);
+            };
+            let value = value
+                .parse()
+                .with_context(|| format!("Invalid {name} value {value:?}"))?;
+            Ok(Some(value))
+        };
+        let required =
+            |name: &str| param(name)?.with_context(|| format!("Missing {name} parameter"));
+
+        let fmc = match product {
+            Product::Turin => Some(required("fmcSPL")?),
+            _ => param("fmcSPL")?,
+        };
+        Ok(Self {
+            fmc,
+            bootloader: required("blSPL")?,
+            tee: required("teeSPL")?,
+            snp: required("snpSPL")?,
+            microcode: required("ucodeSPL")?,
+        })
+    }
+}
+
+impl fmt::Display for Tcb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(fmc) = self.fmc {
+            write!(f, "fmc={fmc} ")?;
+        }
+        write!(
+            f,
+            "bl={} tee={} snp={} ucode={}",
+            self.bootloader, self.tee, self.snp, self.microcode
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENOA: Tcb = Tcb {
+        fmc: None,
+        bootloader: 9,
+        tee: 0,
+        snp: 22,
+        microcode: 213,
+    };
+    const TURIN: Tcb = Tcb {
+        fmc: Some(1),
+        bootloader: 3,
+        tee: 0,
+        snp: 5,
+        microcode: 74,
+    };
+
+    #[test]
+    fn raw_layouts() {
+        let cases = [
+            (Product::Milan, GENOA, [9, 0, 0, 0, 0, 0, 22, 213]),
+            (Product::Genoa, GENOA, [9, 0, 0, 0, 0, 0, 22, 213]),
+            (Product::Turin, TURIN, [1, 3, 0, 5, 0, 0, 0, 74]),
+        ];
+        for (product, tcb, raw) in cases {
+            assert_eq!(tcb.to_raw(product), raw, "{product}");
+            assert_eq!(Tcb::from_raw(product, raw), tcb, "{product}");
+            let version = tcb.to_version(product).unwrap();
+            assert_eq!(Tcb::from_version(product, &version).unwrap(), tcb);
+        }
+    }
+
+    #[test]
+    fn kds_queries() {
+        let cases = [
+            (
+                Product::Genoa,
+                GENOA,
+                "blSPL=09&teeSPL=00&snpSPL=22&ucodeSPL=213",
+            ),
+            (
+                Product::Turin,
+                TURIN,
+                "fmcSPL=01&blSPL=03&teeSPL=00&snpSPL=05&ucodeSPL=74",
+            ),
+        ];
+        for (product, tcb, query) in cases {
+            assert_eq!(tcb.kds_query(), query);
+            assert_eq!(Tcb::from_kds_query(product, query).unwrap(), tcb);
+        }
+        assert_eq!(
+            Tcb::from_kds_query(Product::Milan, "ucodeSPL=213&snpSPL=22&teeSPL=0&blSPL=9").unwrap(),
+            GENOA
+        );
+    }
+
+    #[test]
+    fn bad_kds_queries() {
+        let cases = [
+            (
+                Product::Turin,
+                "blSPL=03&teeSPL=00&snpSPL=05&ucodeSPL=74",
+                "Missing fmcSPL parameter",
+            ),
+            (
+                Product::Genoa,
+                "blSPL=09&teeSPL=00&snpSPL=22",
+                "Missing ucodeSPL parameter",
+            ),
+            (
+                Product::Genoa,
+                "blSPL=09&teeSPL=00&snpSPL=256&ucodeSPL=213",
+                "Invalid snpSPL value \"256\"",
+            ),
+            (
+                Product::Genoa,
+                "fmcSPL=x&blSPL=09&teeSPL=00&snpSPL=22&ucodeSPL=213",
+                "Invalid fmcSPL value \"x\"",
+            ),
+        ];
+        for (product, query, error) in cases {
+            let err = Tcb::from_kds_query(product, query).unwrap_err();
+            assert_eq!(err.to_string(), error, "{query}");
+        }
+    }
+
+    #[test]
+    fn ordering() {
+        let older = Tcb { snp: 21, ..GENOA };
+        let mixed = Tcb {
+            snp: 21,
+            microcode: 214,
+            ..GENOA
+        };
+        assert!(older.is_older_than(&GENOA));
+        assert!(!GENOA.is_older_than(&older));
+        assert!(!GENOA.is_older_than(&GENOA));
+        assert!(!mixed.is_older_than(&GENOA));
+        assert!(Tcb {
+            fmc: Some(0),
+            ..TURIN
+        }
+        .is_older_than(&TURIN));
+    }
+}
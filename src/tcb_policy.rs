@@ -0,0 +1,234 @@
+//! Minimum TCB versions a report must meet, per product line.
+//!
+//! A policy file is TOML or JSON, keyed by product:
+//!
+//! ```toml
+//! [Genoa]
+//! bootloader = 9
+//! tee = 0
+//! snp = 23
+//! microcode = 84
+//!
+//! [Turin]
+//! fmc = 1
+//! snp = 3
+//! ```
+//!
+//! Components left out have no minimum. A product left out is rejected.
+
+use anyhow::bail;
+use serde::Deserialize;
+use sev::firmware::guest::AttestationReport;
+use std::{collections::HashMap, path::Path};
+
+use crate::{config::load_config, product::Product, tcb::Tcb};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MinimumTcb {
+    pub fmc: Option<u8>,
+    pub bootloader: Option<u8>,
+    pub tee: Option<u8>,
+    pub snp: Option<u8>,
+    pub microcode: Option<u8>,
+}
+
+impl MinimumTcb {
+    /// Each component's minimum, named as in `Tcb::components`.
+    fn components(&self) -> [(&'static str, Option<u8>); 5] {
+        [
+            ("fmc", self.fmc),
+            ("bootloader", self.bootloader),
+            ("tee", self.tee),
+            ("snp", self.snp),
+            ("microcode", self.microcode),
+        ]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct TcbPolicy {
+    products: HashMap<Product, MinimumTcb>,
+}
+
+impl TcbPolicy {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let policy: Self = load_config(path, "TCB policy")?;
+
+        for (product, minimum) in &policy.products {
+            if minimum.fmc.is_some() && *product != Product::Turin {
+                bail!(
+                    "TCB policy {} sets an FMC minimum for {product}, which has no FMC",
+                    path.display()
+                );
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Every component of the report's reported, committed, current and
+    /// launch TCBs that is below the minimum, one message each.
+    pub fn violations(
+        &self,
+        report: &AttestationReport,
+        product: Product,
+    ) -> anyhow::Result<Vec<String>> {
+        let Some(minimum) = self.products.get(&product) else {
+            return Ok(vec![format!("TCB policy has no entry for {product}")]);
+        };
+
+        let mut violations = Vec::new();
+        for (name, version) in [
+            ("reported_tcb", &report.reported_tcb),
+            ("committed_tcb", &report.committed_tcb),
+            ("current_tcb", &report.current_tcb),
+            ("launch_tcb", &report.launch_tcb),
+        ] {
+            let tcb = Tcb::from_version(product, version)?;
+            let components = tcb.components();
+            for (component, min) in minimum.components() {
+                let Some(min) = min else {
+                    continue;
+                };
+                let Some((_, actual)) = components.iter().find(|(c, _)| *c == component) else {
+                    continue;
+                };
+                if *actual < min {
+                    violations.push(format!(
+                        "{name} {component} SPL is {actual}, below the minimum {min}"
+                    ));
+                }
+            }
+        }
+        Ok(violations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    const POLICY: &str = r#"
+        [Genoa]
+        bootloader = 10
+        tee = 10
+        snp = 10
+        microcode = 10
+
+        [Turin]
+        fmc = 10
+        bootloader = 10
+        tee = 10
+        snp = 10
+        microcode = 10
+    "#;
+
+    fn policy(contents: &str) -> anyhow::Result<TcbPolicy> {
+        let dir = TempDir::new("tcb-policy");
+        TcbPolicy::load(&dir.write("policy.toml", contents))
+    }
+
+    /// A report whose four TCBs are all `tcb`.
+    fn report(product: Product, tcb: Tcb) -> AttestationReport {
+        let version = tcb.to_version(product).unwrap();
+        let mut report = AttestationReport::default();
+        report.reported_tcb = version;
+        report.committed_tcb = version;
+        report.current_tcb = version;
+        report.launch_tcb = version;
+        report
+    }
+
+    #[test]
+    fn each_component_of_each_tcb() {
+        let policy = policy(POLICY).unwrap();
+        for product in [Product::Genoa, Product::Turin] {
+            let fmc = (product == Product::Turin).then_some(10);
+            let tcb = Tcb {
+                fmc,
+                bootloader: 10,
+                tee: 10,
+                snp: 10,
+                microcode: 10,
+            };
+            let at_minimum = report(product, tcb);
+            assert!(policy.violations(&at_minimum, product).unwrap().is_empty());
+
+            for (name, _) in tcb.components() {
+                let mut older = tcb;
+                match name {
+                    "fmc" => older.fmc = Some(9),
+                    "bootloader" => older.bootloader = 9,
+                    "tee" => older.tee = 9,
+                    "snp" => older.snp = 9,
+                    _ => older.microcode = 9,
+                }
+                let older = older.to_version(product).unwrap();
+                for field in ["reported_tcb", "committed_tcb", "current_tcb", "launch_tcb"] {
+                    let mut report = at_minimum;
+                    match field {
+                        "reported_tcb" => report.reported_tcb = older,
+                        "committed_tcb" => report.committed_tcb = older,
+                        "current_tcb" => report.current_tcb = older,
+                        _ => report.launch_tcb = older,
+                    }
+                    assert_eq!(
+                        policy.violations(&report, product).unwrap(),
+                        [format!("{field} {name} SPL is 9, below the minimum 10")],
+                        "{product}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn components_without_minimum() {
+        let policy = policy("[Genoa]\nsnp = 5\n").unwrap();
+        let report = report(Product::Genoa, Tcb::default());
+        assert_eq!(
+            policy.violations(&report, Product::Genoa).unwrap(),
+            [
+                "reported_tcb snp SPL is 0, below the minimum 5",
+                "committed_tcb snp SPL is 0, below the minimum 5",
+                "current_tcb snp SPL is 0, below the minimum 5",
+                "launch_tcb snp SPL is 0, below the minimum 5",
+            ]
+        );
+    }
+
+    #[test]
+    fn product_without_entry() {
+        let policy = policy(POLICY).unwrap();
+        let report = report(Product::Milan, Tcb::default());
+        assert_eq!(
+            policy.violations(&report, Product::Milan).unwrap(),
+            ["TCB policy has no entry for Milan"]
+        );
+    }
+
+    #[test]
+    fn fmc_minimum_before_turin() {
+        for product in ["Milan", "Genoa"] {
+            let err = policy(&format!("[{product}]\nfmc = 1\n")).unwrap_err();
+            assert!(
+                err.to_string().contains(&format!(
+                    "sets an FMC minimum for {product}, which has no FMC"
+                )),
+                "{err}"
+            );
+        }
+        policy("[Turin]\nfmc = 1\n").unwrap();
+    }
+
+    #[test]
+    fn unknown_component() {
+        let err = policy("[Genoa]\nsvn = 1\n").unwrap_err();
+        assert!(
+            format!("{err:#}").contains("unknown field `svn`"),
+            "{err:#}"
+        );
+    }
+}
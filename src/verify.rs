@@ -0,0 +1,716 @@
+use anyhow::{bail, ensure, Context};
+use openssl::{
+    asn1::Asn1Time,
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    nid::Nid,
+    rsa::Padding,
+    sha::sha384,
+    sign::{RsaPssSaltlen, Verifier},
+    x509::{X509CrlRef, X509Ref},
+};
+use serde::Serialize;
+use sev::firmware::guest::AttestationReport;
+use std::{cmp::Ordering, fmt};
+
+use crate::{
+    certs,
+    decoded::DecodedReport,
+    der,
+    extensions::VcekExtensions,
+    output::{self, OutputFormat},
+    product::Product,
+    report::{self, SigningKey},
+    rules::RuleResult,
+    tcb::Tcb,
+};
+
+const OID_RSASSA_PSS: &str = "1.2.840.113549.1.1.10";
+
+/// SHA-256 fingerprints of the SubjectPublicKeyInfo of AMD's ARKs, for the
+/// products whose ARK ships with the `sev` crate (see `certs::builtin_ca`).
+const AMD_ARK_KEY_FINGERPRINTS: &[(Product, &str)] = &[
+    (
+        Product::Milan,
+        "9f056bee44377e29308cb5ffa895bdfb62d18881fa6bed8d6f075b0204089cb9",
+    ),
+    (
+        Product::Genoa,
+        "429a69c9422aa258ee4d8db5fcda9c6470ef15f8cd5a9cebd6cbc7d90b863831",
+    ),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Passed,
+    Failed,
+    /// Passed, but a problem was let through by policy.
+    Incomplete,
+    Skipped,
+}
+
+/// One verification step.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub check: &'static str,
+    #[serde(skip)]
+    pub label: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Individual findings, e.g. one per policy violation.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+/// Everything `verify` found, printed once verification ends.
+#[derive(Debug, Default, Serialize)]
+pub struct Verification {
+    pub verified: bool,
+    pub signing_key: Option<SigningKey>,
+    pub checks: Vec<Check>,
+    /// The registry image whose measurement the report carries.
+    pub matched_image: Option<MatchedImage>,
+    /// The outcome of every rule from `--rules`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RuleResult>,
+    pub report: Option<DecodedReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchedImage {
+    pub name: String,
+    pub description: String,
+}
+
+impl Verification {
+    fn push(&mut self, check: &'static str, label: &str, status: Status, message: Option<String>) {
+        self.checks.push(Check {
+            check,
+            label: label.to_string(),
+            status,
+            message,
+            details: Vec::new(),
+        });
+    }
+
+    /// Record the outcome of the `check` step, adding `label FAILED` to its error.
+    pub fn check(
+        &mut self,
+        check: &'static str,
+        label: &str,
+        result: anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.check_noted(check, label, result.map(|()| None))
+    }
+
+    /// Like `check`, with an optional note on what passed.
+    pub fn check_noted(
+        &mut self,
+        check: &'static str,
+        label: &str,
+        result: anyhow::Result<Option<String>>,
+    ) -> anyhow::Result<()> {
+        match result {
+            Ok(note) => {
+                self.push(check, label, Status::Passed, note);
+                Ok(())
+            }
+            Err(e) => {
+                self.push(check, label, Status::Failed, Some(format!("{e:#}")));
+                Err(e.context(format!("{label} FAILED")))
+            }
+        }
+    }
+
+    /// Record a step that found `problems`, failing it if there are any.
+    pub fn check_each(
+        &mut self,
+        check: &'static str,
+        label: &str,
+        problems: Vec<String>,
+    ) -> anyhow::Result<()> {
+        if problems.is_empty() {
+            return self.check(check, label, Ok(()));
+        }
+        let message = match problems.as_slice() {
+            [problem] => problem.clone(),
+            problems => format!("{} violations", problems.len()),
+        };
+        self.push(check, label, Status::Failed, Some(message.clone()));
+        if let Some(last) = self.checks.last_mut() {
+            last.details = problems;
+        }
+        Err(anyhow::anyhow!(message).context(format!("{label} FAILED")))
+    }
+
+    pub fn incomplete(&mut self, check: &'static str, label: &str, message: &str) {
+        self.push(check, label, Status::Incomplete, Some(message.to_string()));
+    }
+
+    pub fn skipped(&mut self, check: &'static str, label: &str) {
+        self.push(check, label, Status::Skipped, None);
+    }
+
+    /// Print the outcome in `format` and pass `result` through.
+    pub fn finish(
+        mut self,
+        format: OutputFormat,
+        result: anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.verified = result.is_ok();
+        output::emit(format, &self)?;
+        result
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                Status::Passed => "PASSED",
+                Status::Failed => "FAILED",
+                Status::Incomplete => "INCOMPLETE",
+                Status::Skipped => "SKIPPED",
+            };
+            write!(f, "{} {status}", check.label)?;
+            // The details already say what the message sums up.
+            if !check.details.is_empty() {
+                writeln!(f, ":")?;
+                for detail in &check.details {
+                    writeln!(f, "  {detail}")?;
+                }
+            } else if let Some(message) = &check.message {
+                writeln!(f, ", {message}")?;
+            } else {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The pinned ARK key fingerprint compiled in for `product`, if any.
+pub fn amd_ark_fingerprint(product: Product) -> Option<&'static str> {
+    AMD_ARK_KEY_FINGERPRINTS
+        .iter()
+        .find(|(p, _)| *p == product)
+        .map(|(_, fingerprint)| *fingerprint)
+}
+
+/// Check that `report` was signed by the VCEK or VLEK over bytes 0x0-0x29F.
+pub fn report_signature(report: &AttestationReport, vcek: &X509Ref) -> anyhow::Result<()> {
+    if report.sig_algo != report::SIG_ALGO_ECDSA_P384_SHA384 {
+        bail!("Unsupported report signature algorithm {}", report.sig_algo);
+    }
+
+    let key = vcek
+        .public_key()?
+        .ec_key()
+        .context("Signing certificate does not hold an EC public key")?;
+    if key.group().curve_name() != Some(Nid::SECP384R1) {
+        bail!("Signing certificate public key is not on curve P-384");
+    }
+
+    let bytes = report::to_bytes(report)?;
+    let digest = sha384(&bytes[..report::SIGNED_LEN]);
+    let sig = EcdsaSig::try_from(&report.signature).context("Malformed report signature")?;
+
+    if !sig.verify(&digest, &key)? {
+        bail!("Report signature does not match the signing certificate");
+    }
+
+    Ok(())
+}
+
+/// Validate ARK -> ASK -> VCEK, or ARK -> ASVK -> VLEK, anchoring the ARK on
+/// `ark_fingerprint`.
+pub fn cert_chain(
+    ark: &X509Ref,
+    ask: &X509Ref,
+    vcek: &X509Ref,
+    key: SigningKey,
+    ark_fingerprint: &str,
+) -> anyhow::Result<()> {
+    let fingerprint = certs::key_fingerprint(ark)?;
+    ensure!(
+        fingerprint.eq_ignore_ascii_case(ark_fingerprint),
+        "ARK key {fingerprint} is not the trusted key {ark_fingerprint}"
+    );
+
+    let issuer = key.issuer_name();
+    for (name, cert) in [("ARK", ark), (issuer, ask)] {
+        check_validity(cert).with_context(|| format!("{name} is not currently valid"))?;
+    }
+    check_validity(vcek).with_context(|| format!("{key} is not currently valid"))?;
+
+    pss_signed_by(ark, ark).context("ARK is not self-signed")?;
+    pss_signed_by(ark, ask).with_context(|| format!("{issuer} is not signed by the ARK"))?;
+    pss_signed_by(ask, vcek).with_context(|| format!("{key} is not signed by the {issuer}"))?;
+
+    Ok(())
+}
+
+fn check_validity(cert: &X509Ref) -> anyhow::Result<()> {
+    let now = Asn1Time::days_from_now(0)?;
+    ensure!(
+        cert.not_before().compare(&now)? != Ordering::Greater,
+        "not valid before {}",
+        cert.not_before()
+    );
+    ensure!(
+        cert.not_after().compare(&now)? != Ordering::Less,
+        "expired at {}",
+        cert.not_after()
+    );
+    Ok(())
+}
+
+/// Verify `subject` against `issuer` with the RSASSA-PSS parameters AMD signs
+/// with: SHA-384, MGF1 with SHA-384 and a 48 byte salt.
+fn pss_signed_by(issuer: &X509Ref, subject: &X509Ref) -> anyhow::Result<()> {
+    ensure!(
+        subject.issuer_name().try_cmp(issuer.subject_name())? == Ordering::Equal,
+        "issuer name does not match"
+    );
+    pss_signature(issuer, &subject.to_der()?)
+}
+
+/// Check that `crl` was issued and signed by `issuer`, as a certificate would be.
+pub fn crl_signed_by(issuer: &X509Ref, crl: &X509CrlRef) -> anyhow::Result<()> {
+    ensure!(
+        crl.issuer_name().try_cmp(issuer.subject_name())? == Ordering::Equal,
+        "CRL issuer name does not match"
+    );
+    pss_signature(issuer, &crl.to_der()?)
+}
+
+/// Verify the signature of a DER certificate or CRL, both of which are
+/// `SEQUENCE { tbs, signatureAlgorithm, signatureValue }`.
+fn pss_signature(issuer: &X509Ref, signed: &[u8]) -> anyhow::Result<()> {
+    let (signed, _) = der::expect(signed, der::TAG_SEQUENCE)?;
+    let (tbs, rest) = der::expect(signed.value, der::TAG_SEQUENCE)?;
+    let (algorithm, rest) = der::expect(rest, der::TAG_SEQUENCE)?;
+    let (algorithm, _) = der::expect(algorithm.value, der::TAG_OID)?;
+    let algorithm = der::oid_to_string(algorithm.value)?;
+    ensure!(
+        algorithm == OID_RSASSA_PSS,
+        "signature algorithm is {algorithm}, expected RSASSA-PSS"
+    );
+    let (signature, _) = der::expect(rest, der::TAG_BIT_STRING)?;
+    let signature = match signature.value {
+        [0, signature @ ..] => signature,
+        _ => bail!("malformed signature BIT STRING"),
+    };
+
+    let key = issuer.public_key()?;
+    let mut verifier = Verifier::new(MessageDigest::sha384(), &key)?;
+    verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+    verifier.set_rsa_pss_saltlen(RsaPssSaltlen::custom(48))?;
+    verifier.set_rsa_mgf1_md(MessageDigest::sha384())?;
+
+    ensure!(
+        verifier.verify_oneshot(signature, tbs.raw)?,
+        "RSASSA-PSS signature does not verify"
+    );
+    Ok(())
+}
+
+/// Check that the VCEK was issued for the chip and reported TCB of `report`,
+/// or that the VLEK was issued for its reported TCB.
+pub fn key_matches_report(
+    cert: &X509Ref,
+    report: &AttestationReport,
+    product: Product,
+    key: SigningKey,
+) -> anyhow::Result<()> {
+    let extensions = VcekExtensions::from_cert(cert)?;
+
+    ensure!(
+        extensions.product_name.starts_with(product.name()),
+        "{key} is for product {}, expected {product}",
+        extensions.product_name
+    );
+
+    match key {
+        SigningKey::Vcek => {
+            let hw_id = extensions
+                .hw_id
+                .as_deref()
+                .context("Certificate has no hwID, it is not a VCEK")?;
+            if report::chip_id_masked(report) {
+                bail!(
+                    "Report has an all-zero chip ID (MASK_CHIP_ID), it cannot be matched to a VCEK"
+                );
+            }
+            let chip_id = &report.chip_id[..product.hw_id_len()];
+            ensure!(
+                hw_id == chip_id,
+                "VCEK belongs to chip {}, but the report is from chip {}",
+                hex::encode(hw_id),
+                hex::encode(chip_id)
+            );
+        }
+        SigningKey::Vlek => {
+            ensure!(
+                extensions.hw_id.is_none(),
+                "Certificate carries a hwID, it is a VCEK rather than a VLEK"
+            );
+        }
+        SigningKey::None => bail!("Report is not signed"),
+    }
+
+    let cert_tcb = extensions.tcb();
+    let report_tcb = Tcb::from_version(product, &report.reported_tcb)?;
+    if cert_tcb != report_tcb {
+        let age = if cert_tcb.is_older_than(&report_tcb) {
+            "an older"
+        } else {
+            "a different"
+        };
+        bail!("{key} is for {age} TCB ({cert_tcb}) than the report's reported TCB ({report_tcb})");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crl::{self, CrlPolicy},
+        firmware::ReportProvider,
+        pki::TestCa,
+        rules::RuleStatus,
+        sim::{SimConfig, SimulatedFirmware},
+    };
+    use openssl::{
+        ec::EcKey,
+        pkey::{PKey, Private},
+        x509::X509,
+    };
+    use std::sync::OnceLock;
+
+    const PRODUCT: Product = Product::Genoa;
+
+    /// A test ARK, ASK and ASVK, generated once since RSA-4096 keys are slow.
+    struct Pki {
+        ca: TestCa,
+        asvk: X509,
+        asvk_key: PKey<Private>,
+    }
+
+    fn pki() -> &'static Pki {
+        static PKI: OnceLock<Pki> = OnceLock::new();
+        PKI.get_or_init(|| {
+            let ca = TestCa::generate(PRODUCT).unwrap();
+            let (asvk, asvk_key) = ca.generate_asvk().unwrap();
+            Pki { ca, asvk, asvk_key }
+        })
+    }
+
+    fn extensions(hw_id: Option<&[u8]>, snp_spl: u8) -> VcekExtensions {
+        VcekExtensions {
+            product_name: PRODUCT.vcek_product_name().to_string(),
+            bl_spl: 3,
+            tee_spl: 0,
+            snp_spl,
+            ucode_spl: 210,
+            fmc_spl: None,
+            hw_id: hw_id.map(<[u8]>::to_vec),
+            csp_id: hw_id.is_none().then(|| "test-csp".to_string()),
+        }
+    }
+
+    fn config(signing_key: SigningKey) -> SimConfig {
+        let mut config = SimConfig {
+            signing_key,
+            ..SimConfig::default()
+        };
+        config.tcb.bootloader = 3;
+        config.tcb.snp = 22;
+        config.tcb.microcode = 210;
+        config
+    }
+
+    fn vcek_for(config: &SimConfig, snp_spl: u8) -> (X509, EcKey<Private>) {
+        let hw_id = &config.chip_id[..PRODUCT.hw_id_len()];
+        pki()
+            .ca
+            .issue_vcek(&extensions(Some(hw_id), snp_spl))
+            .unwrap()
+    }
+
+    fn sim_report(config: SimConfig, key: EcKey<Private>) -> AttestationReport {
+        SimulatedFirmware::new(config, key)
+            .get_report(None, Some([0x5a; 64]), None)
+            .unwrap()
+    }
+
+    fn ark_fingerprint() -> String {
+        certs::key_fingerprint(&pki().ca.ark).unwrap()
+    }
+
+    #[test]
+    fn vcek_report_verifies() {
+        let config = config(SigningKey::Vcek);
+        let (vcek, key) = vcek_for(&config, 22);
+        let report = sim_report(config, key);
+        let ca = &pki().ca;
+
+        cert_chain(
+            &ca.ark,
+            &ca.ask,
+            &vcek,
+            SigningKey::Vcek,
+            &ark_fingerprint(),
+        )
+        .unwrap();
+        key_matches_report(&vcek, &report, PRODUCT, SigningKey::Vcek).unwrap();
+        report_signature(&report, &vcek).unwrap();
+    }
+
+    #[test]
+    fn vlek_report_verifies() {
+        let pki = pki();
+        let (vlek, key) = pki
+            .ca
+            .issue_vlek(&pki.asvk, &pki.asvk_key, &extensions(None, 22))
+            .unwrap();
+        let report = sim_report(config(SigningKey::Vlek), key);
+
+        assert_eq!(report::signing_key(&report).unwrap(), SigningKey::Vlek);
+        cert_chain(
+            &pki.ca.ark,
+            &pki.asvk,
+            &vlek,
+            SigningKey::Vlek,
+            &ark_fingerprint(),
+        )
+        .unwrap();
+        key_matches_report(&vlek, &report, PRODUCT, SigningKey::Vlek).unwrap();
+        report_signature(&report, &vlek).unwrap();
+    }
+
+    #[test]
+    fn chain_with_other_ark_fails() {
+        let config = config(SigningKey::Vcek);
+        let (vcek, _) = vcek_for(&config, 22);
+        let ca = &pki().ca;
+        let err = cert_chain(
+            &ca.ark,
+            &ca.ask,
+            &vcek,
+            SigningKey::Vcek,
+            amd_ark_fingerprint(PRODUCT).unwrap(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("is not the trusted key"), "{err}");
+    }
+
+    #[test]
+    fn flipped_report_byte_fails_signature() {
+        let config = config(SigningKey::Vcek);
+        let (vcek, key) = vcek_for(&config, 22);
+        let report = sim_report(config, key);
+
+        let mut bytes = report::to_bytes(&report).unwrap();
+        bytes[0x50] ^= 1;
+        let tampered = report::from_bytes(&bytes).unwrap();
+        let err = report_signature(&tampered, &vcek).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Report signature does not match the signing certificate"
+        );
+    }
+
+    #[test]
+    fn vcek_for_other_chip_fails() {
+        let config = config(SigningKey::Vcek);
+        let (_, key) = vcek_for(&config, 22);
+        let other_chip = [0x11; 64];
+        let (vcek, _) = pki()
+            .ca
+            .issue_vcek(&extensions(Some(&other_chip), 22))
+            .unwrap();
+        let report = sim_report(config, key);
+
+        let err = key_matches_report(&vcek, &report, PRODUCT, SigningKey::Vcek).unwrap_err();
+        assert!(
+            err.to_string().starts_with("VCEK belongs to chip 1111"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn tcb_mismatch_fails() {
+        let config = config(SigningKey::Vcek);
+        let (vcek, key) = vcek_for(&config, 21);
+        let report = sim_report(config, key);
+
+        let err = key_matches_report(&vcek, &report, PRODUCT, SigningKey::Vcek).unwrap_err();
+        assert!(
+            err.to_string().starts_with("VCEK is for an older TCB"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn revoked_ask_fails() {
+        let config = config(SigningKey::Vcek);
+        let (vcek, _) = vcek_for(&config, 22);
+        let ca = &pki().ca;
+        let check = |crl: &openssl::x509::X509CrlRef| {
+            crl::check(
+                crl,
+                &ca.ark,
+                &ca.ask,
+                &vcek,
+                SigningKey::Vcek,
+                CrlPolicy::Strict,
+            )
+        };
+
+        assert!(check(&ca.crl(&[], 30).unwrap()).unwrap());
+        let serial = ca.ask.serial_number().to_bn().unwrap();
+        let err = check(&ca.crl(&[serial], 30).unwrap()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("ASK serial 0x010001 was revoked on"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn revoked_vcek_fails() {
+        let config = config(SigningKey::Vcek);
+        let (vcek, _) = vcek_for(&config, 22);
+        let ca = &pki().ca;
+        let check = |crl: &openssl::x509::X509CrlRef| {
+            crl::check(
+                crl,
+                &ca.ark,
+                &ca.ask,
+                &vcek,
+                SigningKey::Vcek,
+                CrlPolicy::Strict,
+            )
+        };
+
+        let crl = ca.issue_crl(&ca.ask, &ca.ask_key, &[], 30).unwrap();
+        assert!(check(&crl).unwrap());
+        let serial = vcek.serial_number().to_bn().unwrap();
+        let crl = ca.issue_crl(&ca.ask, &ca.ask_key, &[serial], 30).unwrap();
+        let err = check(&crl).unwrap_err();
+        assert!(err.to_string().starts_with("VCEK serial 0x"), "{err}");
+        assert!(err.to_string().contains("was revoked on"), "{err}");
+    }
+
+    #[test]
+    fn display_lists_every_check() {
+        let mut verification = Verification::default();
+        verification.check("chain", "Chain", Ok(())).unwrap();
+        verification.skipped("revocation", "Revocation");
+        verification.incomplete("crl", "CRL", "no CRL cached");
+        let _ = verification.check("signature", "Signature", Err(anyhow::anyhow!("bad")));
+        let _ = verification.check_each("tcb", "TCB", vec!["a".into(), "b".into()]);
+        verification.push("rules", "Rules", Status::Failed, None);
+        assert_eq!(
+            verification.to_string(),
+            "Chain PASSED\n\
+             Revocation SKIPPED\n\
+             CRL INCOMPLETE, no CRL cached\n\
+             Signature FAILED, bad\n\
+             TCB FAILED:\n  a\n  b\n\
+             Rules FAILED\n"
+        );
+    }
+
+    #[test]
+    fn pinned_arks_match_bundled_certificates() {
+        for product in [Product::Milan, Product::Genoa, Product::Turin] {
+            let bundled = certs::builtin_ca(product).unwrap().map(|(ark, _)| ark);
+            let fingerprint = bundled.map(|ark| certs::key_fingerprint(&ark).unwrap());
+            assert_eq!(
+                fingerprint.as_deref(),
+                amd_ark_fingerprint(product),
+                "{product}"
+            );
+        }
+    }
+
+    /// Validate `value` against `schema/<name>`, which may refer to the
+    /// report schema.
+    fn assert_matches_schema(name: &str, value: &impl Serialize) {
+        let load = |name: &str| -> serde_json::Value {
+            let path = format!("{}/schema/{name}", env!("CARGO_MANIFEST_DIR"));
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+        };
+        let report_schema = load("report.schema.json");
+        let report_id = report_schema["$id"].as_str().unwrap().to_string();
+        let validator = jsonschema::options()
+            .with_resource(
+                report_id,
+                jsonschema::Resource::from_contents(report_schema),
+            )
+            .build(&load(name))
+            .unwrap();
+        let value = serde_json::to_value(value).unwrap();
+        let errors: Vec<_> = validator
+            .iter_errors(&value)
+            .map(|e| format!("{}: {e}", e.instance_path()))
+            .collect();
+        assert!(errors.is_empty(), "{name}: {errors:#?}");
+    }
+
+    #[test]
+    fn outputs_match_schemas() {
+        let config = config(SigningKey::Vcek);
+        let (_, key) = vcek_for(&config, 22);
+        let report = sim_report(config, key);
+        let decoded = DecodedReport::new(&report, PRODUCT).unwrap();
+        assert_matches_schema("report.schema.json", &decoded);
+
+        let mut verification = Verification {
+            signing_key: Some(SigningKey::Vcek),
+            matched_image: Some(MatchedImage {
+                name: "guest".to_string(),
+                description: "Test guest".to_string(),
+            }),
+            rules: vec![
+                RuleResult {
+                    name: "debug_off".to_string(),
+                    status: RuleStatus::Passed,
+                    message: None,
+                },
+                RuleResult {
+                    name: "tenant".to_string(),
+                    status: RuleStatus::Error,
+                    message: Some("Unknown claim tenant".to_string()),
+                },
+            ],
+            report: Some(decoded),
+            ..Verification::default()
+        };
+        verification.check("cert_chain", "Chain", Ok(())).unwrap();
+        verification.incomplete("revocation", "Revocation", "no CRL cached");
+        verification
+            .check_noted("key_matches_report", "Key", Ok(Some("VCEK".to_string())))
+            .unwrap();
+        verification
+            .check("report_signature", "Signature", Ok(()))
+            .unwrap();
+        verification.skipped("tcb_policy", "TCB policy");
+        verification
+            .check("measurement", "Measurement", Ok(()))
+            .unwrap();
+        let _ = verification.check_each("rules", "Rules", vec!["tenant".to_string()]);
+        assert_matches_schema("verify.schema.json", &verification);
+
+        // A verification that failed before the report was read.
+        let mut failed = Verification::default();
+        let _ = failed.check("cert_chain", "Chain", Err(anyhow::anyhow!("bad ASK")));
+        assert_matches_schema("verify.schema.json", &failed);
+    }
+}
@@ -0,0 +1,132 @@
+//! Initial VMSA pages of an SNP guest's vCPUs as QEMU/KVM creates them.
+//!
+//! Offsets follow the SEV-ES save area layout in the AMD64 APM, Vol. 2,
+//! Table B-4. Fields not set here are zero.
+
+use clap::ValueEnum;
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// Where the BSP starts: the x86 reset vector.
+const BSP_EIP: u32 = 0xffff_fff0;
+
+const ES: usize = 0x000;
+const CS: usize = 0x010;
+const SS: usize = 0x020;
+const DS: usize = 0x030;
+const FS: usize = 0x040;
+const GS: usize = 0x050;
+const GDTR: usize = 0x060;
+const LDTR: usize = 0x070;
+const IDTR: usize = 0x080;
+const TR: usize = 0x090;
+const EFER: usize = 0x0d0;
+const CR4: usize = 0x148;
+const CR0: usize = 0x158;
+const DR7: usize = 0x160;
+const DR6: usize = 0x168;
+const RFLAGS: usize = 0x170;
+const RIP: usize = 0x178;
+const G_PAT: usize = 0x268;
+const RDX: usize = 0x310;
+const SEV_FEATURES: usize = 0x3b0;
+const XCR0: usize = 0x3e8;
+const MXCSR: usize = 0x408;
+const X87_FCW: usize = 0x410;
+
+/// QEMU CPU models, by the family, model and stepping they report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum VcpuType {
+    #[value(
+        name = "EPYC",
+        alias = "EPYC-v1",
+        alias = "EPYC-v2",
+        alias = "EPYC-IBPB",
+        alias = "EPYC-v3",
+        alias = "EPYC-v4"
+    )]
+    Epyc,
+    #[value(
+        name = "EPYC-Rome",
+        alias = "EPYC-Rome-v1",
+        alias = "EPYC-Rome-v2",
+        alias = "EPYC-Rome-v3"
+    )]
+    EpycRome,
+    #[value(name = "EPYC-Milan", alias = "EPYC-Milan-v1", alias = "EPYC-Milan-v2")]
+    EpycMilan,
+    #[value(name = "EPYC-Genoa", alias = "EPYC-Genoa-v1")]
+    EpycGenoa,
+}
+
+impl VcpuType {
+    /// The CPUID leaf 1 EAX signature, which KVM puts in RDX at reset.
+    pub fn signature(self) -> u32 {
+        let (family, model, stepping) = match self {
+            VcpuType::Epyc => (23, 1, 2),
+            VcpuType::EpycRome => (23, 49, 0),
+            VcpuType::EpycMilan => (25, 1, 1),
+            VcpuType::EpycGenoa => (25, 17, 0),
+        };
+        cpuid_signature(family, model, stepping)
+    }
+}
+
+pub fn cpuid_signature(family: u32, model: u32, stepping: u32) -> u32 {
+    let (family_low, family_high) = match family {
+        0..=0xf => (family, 0),
+        _ => (0xf, (family - 0xf) & 0xff),
+    };
+    (family_high << 20)
+        | ((model >> 4 & 0xf) << 16)
+        | (family_low << 8)
+        | ((model & 0xf) << 4)
+        | (stepping & 0xf)
+}
+
+/// The VMSA page of each of `vcpus` vCPUs: the BSP starts at the reset
+/// vector and the APs at `ap_eip`.
+pub fn pages(vcpus: u32, ap_eip: u32, signature: u32, features: u64) -> Vec<[u8; PAGE_SIZE]> {
+    (0..vcpus)
+        .map(|i| page(if i == 0 { BSP_EIP } else { ap_eip }, signature, features))
+        .collect()
+}
+
+fn page(eip: u32, signature: u32, features: u64) -> [u8; PAGE_SIZE] {
+    let mut page = [0u8; PAGE_SIZE];
+    let mut segment = |offset: usize, selector: u16, attrib: u16, base: u64| {
+        page[offset..offset + 2].copy_from_slice(&selector.to_le_bytes());
+        page[offset + 2..offset + 4].copy_from_slice(&attrib.to_le_bytes());
+        page[offset + 4..offset + 8].copy_from_slice(&0xffffu32.to_le_bytes());
+        page[offset + 8..offset + 16].copy_from_slice(&base.to_le_bytes());
+    };
+    segment(ES, 0, 0x93, 0);
+    segment(CS, 0xf000, 0x9b, u64::from(eip & 0xffff_0000));
+    segment(SS, 0, 0x93, 0);
+    segment(DS, 0, 0x93, 0);
+    segment(FS, 0, 0x93, 0);
+    segment(GS, 0, 0x93, 0);
+    segment(GDTR, 0, 0, 0);
+    segment(LDTR, 0, 0x82, 0);
+    segment(IDTR, 0, 0, 0);
+    segment(TR, 0, 0x8b, 0);
+
+    let mut put = |offset: usize, bytes: &[u8]| {
+        page[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    // KVM sets EFER.SVME and CR4.MCE.
+    put(EFER, &0x1000u64.to_le_bytes());
+    put(CR4, &0x40u64.to_le_bytes());
+    put(CR0, &0x10u64.to_le_bytes());
+    put(DR7, &0x400u64.to_le_bytes());
+    put(DR6, &0xffff_0ff0u64.to_le_bytes());
+    put(RFLAGS, &0x2u64.to_le_bytes());
+    put(RIP, &u64::from(eip & 0xffff).to_le_bytes());
+    put(G_PAT, &0x0007_0406_0007_0406u64.to_le_bytes());
+    put(RDX, &u64::from(signature).to_le_bytes());
+    put(SEV_FEATURES, &features.to_le_bytes());
+    put(XCR0, &0x1u64.to_le_bytes());
+    put(MXCSR, &0x1f80u32.to_le_bytes());
+    put(X87_FCW, &0x37fu16.to_le_bytes());
+    page
+}